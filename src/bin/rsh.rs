@@ -1,42 +1,135 @@
 use std::error::Error;
-use std::path::PathBuf;
+use std::io::{self, IsTerminal, Read};
+use std::path::Path;
 
 use rustyline::Editor;
 use rustyline::history::DefaultHistory;
 
-use rsh::rsh::{Session, read_block, Input, handle_delete_command};
+use rsh::rsh::{Session, read_block, Input, handle_command, CommandResult, resolve_failed_block, run_script, parse_args, Options};
+use rsh::rsh::cli::{HELP, USAGE};
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let mut rl = Editor::<(), DefaultHistory>::new()?;
-    let mut session = Session::new(None::<PathBuf>);
-
-    loop {
-        match read_block(&mut rl) {
-            Ok(Some(Input::Command(cmd))) => {
-                if cmd.starts_with(":delete ") {
-                    handle_delete_command(&cmd, &mut session);
-                } else {
-                    match cmd.as_str() {
-                        ":q" | ":quit" => break,
-                        ":reset" => {
-                            session.reset();
-                            println!("Session reset.");
-                        }
-                        ":show" => {
-                            session.show();
-                        }
-                        _ => {
-                            eprintln!("Unknown command: {cmd}");
-                        }
-                    }
-                }
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("rsh: {e}");
+            eprintln!("{USAGE}");
+            std::process::exit(2);
+        }
+    };
+    if options.help {
+        println!("{HELP}");
+        return Ok(());
+    }
+    if options.version {
+        println!("rsh {}", env!("CARGO_PKG_VERSION"));
+        return Ok(());
+    }
+
+    let mut session = if options.standalone {
+        Session::standalone()?
+    } else {
+        Session::new(options.project.as_ref())
+    };
+    if let Err(e) = session.check_project() {
+        eprintln!("rsh: {e}");
+        std::process::exit(2);
+    }
+    if !options.features.is_empty() || options.release {
+        let mut cargo = session.cargo_options().clone();
+        if !options.features.is_empty() {
+            cargo.features = options.features.clone();
+        }
+        cargo.release |= options.release;
+        session.set_cargo_options(cargo);
+    }
+    session.set_cleanup(!options.no_cleanup);
+
+    if options.resume {
+        match session.resume() {
+            Ok(()) => eprintln!(
+                "rsh: resumed the last session ({} PREAMBLE and {} BODY lines).",
+                session.preamble().len(),
+                session.body().len()
+            ),
+            Err(e) => eprintln!("rsh: --resume: {e}"),
+        }
+    }
+
+    if let Some(request) = &options.mode {
+        let switched = session
+            .resolve_mode(request)
+            .map_err(|e| e.into())
+            .and_then(|mode| session.switch_mode(mode));
+        if let Err(e) = switched {
+            eprintln!("rsh: --mode: {e}");
+            session.cleanup();
+            std::process::exit(2);
+        }
+    }
+
+    // `--load` scripts prepare the session; `--exec`, `rsh run` and piped input replace the prompt.
+    let batch = batch_input(&options);
+    let interactive = batch.is_none();
+    let mut scripts: Vec<_> = options.load.iter().map(|path| read_script(path)).collect();
+    scripts.extend(batch.into_iter().flatten());
+    let result = run_scripts(&mut session, scripts, options.keep_going);
+    if !interactive {
+        session.cleanup();
+        match result {
+            Ok(0) => return Ok(()),
+            Ok(failures) => {
+                eprintln!("rsh: {failures} failure(s).");
+                std::process::exit(1);
             }
-            Ok(Some(Input::Code(block))) => {
-                session.add_code_block(&block);
-                if let Err(e) = session.run() {
+            Err(e) => {
+                eprintln!("rsh: {e}");
+                std::process::exit(2);
+            }
+        }
+    }
+    if let Err(e) = result {
+        eprintln!("rsh: {e}");
+    }
+
+    if let Err(e) = session.start_warmup() {
+        eprintln!("rsh: warm-up build not started: {e}");
+    }
+
+    let mut rl = Editor::<(), DefaultHistory>::new()?;
+    if let Some(path) = options.history_file.as_ref().filter(|path| path.exists()) {
+        if let Err(e) = rl.load_history(path) {
+            eprintln!("rsh: could not load history from {}: {e}", path.display());
+        }
+    }
+    'repl: loop {
+        let prompt = session.prompt();
+        match read_block(&mut rl, &prompt) {
+            Ok(Some(Input::Command(cmd))) => match handle_command(&cmd, &mut session) {
+                Ok(CommandResult::Quit) => break,
+                Ok(_) => {}
+                Err(e) => {
                     eprintln!("Internal rsh error: {e}");
                     break;
                 }
+            },
+            Ok(Some(Input::Code(block))) => {
+                let mut block = Some(block);
+                // An edited block under the `ask` policy runs again like a new one.
+                while let Some(code) = block.take() {
+                    session.add_code_block(&code);
+                    if let Err(e) = session.run() {
+                        eprintln!("Internal rsh error: {e}");
+                        break 'repl;
+                    }
+                    match resolve_failed_block(&mut rl, &mut session) {
+                        Ok(edited) => block = edited,
+                        Err(e) => {
+                            eprintln!("Readline error: {e}");
+                            break 'repl;
+                        }
+                    }
+                }
             }
             Ok(None) => {
                 // EOF (Ctrl-D) – exit session.
@@ -48,8 +141,57 @@ fn main() -> Result<(), Box<dyn Error>> {
             }
         }
     }
-    // cleanup
+    if let Some(path) = &options.history_file {
+        if let Err(e) = rl.save_history(path) {
+            eprintln!("rsh: could not save history to {}: {e}", path.display());
+        }
+    }
     session.cleanup();
 
     Ok(())
 }
+
+/// The scripts to run instead of the prompt, with their names for messages:
+/// each `--exec`, the file given by `rsh run`, or stdin when it isn't a
+/// terminal. `None` starts the prompt.
+fn batch_input(options: &Options) -> Option<Vec<io::Result<(String, String)>>> {
+    let read_stdin = || {
+        let mut text = String::new();
+        io::stdin().read_to_string(&mut text)?;
+        Ok(("<stdin>".to_string(), text))
+    };
+    if !options.exec.is_empty() {
+        return Some(options.exec.iter().map(|code| Ok(("--exec".to_string(), code.clone()))).collect());
+    }
+    match &options.script {
+        Some(path) if path.as_os_str() == "-" => Some(vec![read_stdin()]),
+        Some(path) => Some(vec![read_script(path)]),
+        None if !io::stdin().is_terminal() => Some(vec![read_stdin()]),
+        None => None,
+    }
+}
+
+fn read_script(path: &Path) -> io::Result<(String, String)> {
+    std::fs::read_to_string(path)
+        .map(|text| (path.display().to_string(), text))
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", path.display())))
+}
+
+/// Run scripts in order, stopping after the first one with a failure unless
+/// `keep_going` is set. Returns the number of failures; `Err` for an unreadable
+/// script or an internal error.
+fn run_scripts(
+    session: &mut Session,
+    scripts: Vec<io::Result<(String, String)>>,
+    keep_going: bool,
+) -> Result<usize, Box<dyn Error>> {
+    let mut failures = 0;
+    for script in scripts {
+        let (source, text) = script?;
+        failures += run_script(session, &source, &text, keep_going)?;
+        if failures > 0 && !keep_going {
+            break;
+        }
+    }
+    Ok(failures)
+}
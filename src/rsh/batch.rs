@@ -0,0 +1,121 @@
+use std::error::Error;
+
+use super::input::{command_continues, handle_command, CommandResult, Input};
+use super::session::{RunOutcome, Session};
+
+/// One block or meta-command of a script, with the line it starts on (1-based).
+pub struct ScriptItem {
+    pub line: usize,
+    pub input: Input,
+}
+
+/// Split a script into blocks and meta-commands the same way the REPL reads
+/// them: a line starting with `:` at the start of a block is a command, blank
+/// lines end a block. A `#!/` first line is skipped, so scripts can be executable.
+pub fn parse_script(text: &str) -> Vec<ScriptItem> {
+    let mut items = Vec::new();
+    let mut block: Vec<&str> = Vec::new();
+    // A `:check {` command still collecting lines.
+    let mut command: Option<String> = None;
+    let mut start = 0;
+
+    for (index, line) in text.lines().enumerate() {
+        let trimmed = line.trim();
+        if index == 0 && trimmed.starts_with("#!/") {
+            continue;
+        }
+        if let Some(cmd) = command.as_mut() {
+            cmd.push('\n');
+            cmd.push_str(line);
+            if !command_continues(cmd) {
+                items.push(ScriptItem {
+                    line: start,
+                    input: Input::Command(command.take().unwrap_or_default()),
+                });
+            }
+            continue;
+        }
+        if block.is_empty() && trimmed.starts_with(':') {
+            if command_continues(trimmed) {
+                start = index + 1;
+                command = Some(trimmed.to_string());
+                continue;
+            }
+            items.push(ScriptItem {
+                line: index + 1,
+                input: Input::Command(trimmed.to_string()),
+            });
+            continue;
+        }
+        if trimmed.is_empty() {
+            if !block.is_empty() {
+                items.push(ScriptItem {
+                    line: start,
+                    input: Input::Code(block.join("\n")),
+                });
+                block.clear();
+            }
+            continue;
+        }
+        if block.is_empty() {
+            start = index + 1;
+        }
+        block.push(line);
+    }
+    // Unlike at the prompt, end of input also ends the last block.
+    if let Some(cmd) = command {
+        items.push(ScriptItem {
+            line: start,
+            input: Input::Command(cmd),
+        });
+    }
+    if !block.is_empty() {
+        items.push(ScriptItem {
+            line: start,
+            input: Input::Code(block.join("\n")),
+        });
+    }
+    items
+}
+
+/// Run a script through `session`, stopping at the first failed block or
+/// command (unknown, or one that reported an error) unless `keep_going` is set. `source` names the script in
+/// messages (`script.rsh`, or `<stdin>`). Returns the number of failures.
+///
+/// Blocks that fail under the `ask` policy are dropped, since nobody is there to answer.
+pub fn run_script(session: &mut Session, source: &str, text: &str, keep_going: bool) -> Result<usize, Box<dyn Error>> {
+    let mut failures = 0;
+    for item in parse_script(text) {
+        let failure = match &item.input {
+            Input::Command(cmd) => match handle_command(cmd, session)? {
+                CommandResult::Handled => None,
+                CommandResult::Quit => break,
+                CommandResult::Unknown => Some(format!("unknown command {cmd}")),
+                CommandResult::Failed => Some(format!("command failed: {cmd}")),
+            },
+            Input::Code(code) => {
+                session.add_code_block(code);
+                let outcome = session.run()?;
+                if session.pending_failure().is_some() {
+                    session.drop_last_block();
+                }
+                (outcome != RunOutcome::Success).then(|| format!("block failed ({outcome})"))
+            }
+        };
+
+        let Some(failure) = failure else {
+            continue;
+        };
+        failures += 1;
+        eprintln!("rsh: {}:{}: {}", source, item.line, failure);
+        if let Input::Code(code) = &item.input {
+            for line in code.lines() {
+                eprintln!("    {line}");
+            }
+        }
+        if !keep_going {
+            break;
+        }
+    }
+    Ok(failures)
+}
@@ -0,0 +1,249 @@
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use toml::{Table, Value};
+
+use super::utils::target_dir;
+
+/// Cached executables kept per project; older ones are removed beyond this.
+const MAX_ENTRIES: usize = 32;
+
+/// Larger source files are keyed by size and modification time, not contents.
+const MAX_HASHED_FILE: u64 = 1 << 20;
+
+/// Environment variables cargo passes on to rustc as flags.
+const RUSTFLAGS_VARS: [&str; 3] = ["RUSTFLAGS", "CARGO_ENCODED_RUSTFLAGS", "CARGO_BUILD_RUSTFLAGS"];
+
+/// Built `__rsh` executables keyed by everything that goes into the build, so an
+/// identical session (e.g. after `:delete` and re-adding the same line, or switching
+/// back to a previous mode) runs without invoking cargo.
+///
+/// Entries live in `<target dir>/rsh-cache/<key>` and survive rsh restarts, so the
+/// key uses a stable hash rather than `std`'s randomly seeded one.
+pub struct BuildCache {
+    dir: PathBuf,
+    project_dir: PathBuf,
+    /// `rustc -vV`, so a toolchain update invalidates every entry.
+    host: Option<String>,
+}
+
+impl BuildCache {
+    pub fn new<P: AsRef<Path>>(project_dir: P) -> Self {
+        let project_dir = project_dir.as_ref().to_path_buf();
+        BuildCache {
+            dir: target_dir(&project_dir).join("rsh-cache"),
+            project_dir,
+            host: None,
+        }
+    }
+
+    /// Digest of what goes into every build besides the generated source and
+    /// the lock file: the compiler, the sources of the package and its path
+    /// dependencies, cargo's config files and the rustflags variables. Computed
+    /// once per build and passed to [`BuildCache::key`].
+    pub fn inputs(&mut self) -> u64 {
+        let host = self.host.get_or_insert_with(|| {
+            Command::new("rustc")
+                .arg("-vV")
+                .output()
+                .map(|o| String::from_utf8_lossy(&o.stdout).into_owned())
+                .unwrap_or_default()
+        });
+
+        let mut hash = Fnv::new();
+        hash.write(host.as_bytes());
+        let mut visited = BTreeSet::new();
+        hash_package(&self.project_dir, &mut visited, &mut hash);
+
+        let mut configs: Vec<PathBuf> = self.project_dir.ancestors().map(|dir| dir.join(".cargo")).collect();
+        configs.extend(std::env::var_os("CARGO_HOME").map(PathBuf::from));
+        for dir in configs {
+            for file in ["config.toml", "config"] {
+                hash.write(&fs::read(dir.join(file)).unwrap_or_default());
+            }
+        }
+        for var in RUSTFLAGS_VARS {
+            hash.write(std::env::var(var).unwrap_or_default().as_bytes());
+        }
+        hash.finish()
+    }
+
+    /// The cache key for building `source` with `profile` in `mode`: the generated
+    /// source, the project's `Cargo.toml` and `Cargo.lock` (dependencies and
+    /// features), the profile, the mode and the [`BuildCache::inputs`] digest.
+    pub fn key(&self, inputs: u64, source: &str, profile: &str, mode: &str) -> String {
+        let mut hash = Fnv::new();
+        hash.write(source.as_bytes());
+        for file in ["Cargo.toml", "Cargo.lock"] {
+            hash.write(&fs::read(self.project_dir.join(file)).unwrap_or_default());
+        }
+        hash.write(profile.as_bytes());
+        hash.write(mode.as_bytes());
+        hash.write(&inputs.to_le_bytes());
+        format!("{:016x}", hash.finish())
+    }
+
+    /// The cached executable for `key`, if there is one.
+    pub fn get(&self, key: &str) -> Option<PathBuf> {
+        let path = self.path(key);
+        path.is_file().then_some(path)
+    }
+
+    /// Copy a freshly built executable into the cache and return the cached copy,
+    /// which cargo won't overwrite on the next build.
+    pub fn insert(&self, key: &str, executable: &Path) -> Result<PathBuf, Box<dyn Error>> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.path(key);
+        fs::copy(executable, &path)?;
+        self.prune();
+        Ok(path)
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}{}", key, std::env::consts::EXE_SUFFIX))
+    }
+
+    /// Remove the oldest entries beyond [`MAX_ENTRIES`]. Failures only cost disk space.
+    fn prune(&self) {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return;
+        };
+        let mut entries: Vec<_> = entries
+            .filter_map(Result::ok)
+            .filter_map(|e| Some((e.metadata().ok()?.modified().ok()?, e.path())))
+            .collect();
+        if entries.len() <= MAX_ENTRIES {
+            return;
+        }
+        entries.sort();
+        for (_, path) in &entries[..entries.len() - MAX_ENTRIES] {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Hash the source files of the package in `dir`, then those of its path
+/// dependencies, each package once: `Cargo.toml`, `src/`, the build script and
+/// the `[lib]` and `[[bin]]` paths. Generated `__rsh` binaries are left out.
+fn hash_package(dir: &Path, visited: &mut BTreeSet<PathBuf>, hash: &mut Fnv) {
+    let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+    if !visited.insert(dir.clone()) {
+        return;
+    }
+    let text = fs::read_to_string(dir.join("Cargo.toml")).unwrap_or_default();
+    hash.write(text.as_bytes());
+    hash_dir(&dir.join("src"), hash);
+    let Ok(manifest) = text.parse::<Table>() else {
+        return;
+    };
+
+    let build = manifest.get("package").and_then(|p| p.get("build"));
+    match build {
+        Some(Value::String(path)) => hash_file(&dir.join(path), hash),
+        Some(Value::Boolean(false)) => {}
+        _ => hash_file(&dir.join("build.rs"), hash),
+    }
+    let bins = manifest.get("bin").and_then(Value::as_array).into_iter().flatten();
+    for target in manifest.get("lib").into_iter().chain(bins) {
+        let Some(path) = target.get("path").and_then(Value::as_str).map(|path| dir.join(path)) else {
+            continue;
+        };
+        // A target outside `src/` may have modules next to it, but never hash
+        // the whole package directory.
+        match path.parent().filter(|parent| *parent != dir && !parent.starts_with(dir.join("src"))) {
+            Some(parent) => hash_dir(parent, hash),
+            None => hash_file(&path, hash),
+        }
+    }
+
+    for path in path_dependencies(&manifest) {
+        hash_package(&dir.join(path), visited, hash);
+    }
+}
+
+/// Hash the names and contents of the files under `dir`, in a fixed order.
+/// Symlinks are hashed by their target, not followed. Hidden entries, `target`
+/// directories and generated `__rsh` binaries are skipped.
+fn hash_dir(dir: &Path, hash: &mut Fnv) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut entries: Vec<(PathBuf, fs::FileType)> = entries
+        .filter_map(Result::ok)
+        .filter_map(|e| Some((e.path(), e.file_type().ok()?)))
+        .collect();
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    for (path, file_type) in entries {
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let generated = name.starts_with("__rsh") && dir.ends_with("bin");
+        if name.starts_with('.') || name == "target" || generated {
+            continue;
+        }
+        if file_type.is_symlink() {
+            hash.write(name.as_bytes());
+            hash.write(fs::read_link(&path).unwrap_or_default().as_os_str().as_encoded_bytes());
+        } else if file_type.is_dir() {
+            hash_dir(&path, hash);
+        } else {
+            hash_file(&path, hash);
+        }
+    }
+}
+
+/// Hash a file's name and contents; files larger than [`MAX_HASHED_FILE`] are
+/// hashed by size and modification time instead.
+fn hash_file(path: &Path, hash: &mut Fnv) {
+    let Ok(metadata) = fs::metadata(path) else {
+        return;
+    };
+    hash.write(path.file_name().unwrap_or_default().as_encoded_bytes());
+    if metadata.len() > MAX_HASHED_FILE {
+        hash.write(&metadata.len().to_le_bytes());
+        let modified = metadata.modified().ok().and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok());
+        hash.write(&modified.unwrap_or_default().as_nanos().to_le_bytes());
+    } else {
+        hash.write(&fs::read(path).unwrap_or_default());
+    }
+}
+
+/// The `path` of every dependency in `[dependencies]`, `[build-dependencies]`
+/// and their `[target.'...']` forms.
+fn path_dependencies(manifest: &Table) -> Vec<String> {
+    let mut tables: Vec<&Value> = Vec::new();
+    for key in ["dependencies", "build-dependencies"] {
+        tables.extend(manifest.get(key));
+        if let Some(targets) = manifest.get("target").and_then(Value::as_table) {
+            tables.extend(targets.values().filter_map(|target| target.get(key)));
+        }
+    }
+    tables
+        .into_iter()
+        .filter_map(Value::as_table)
+        .flat_map(|deps| deps.values())
+        .filter_map(|dep| dep.get("path").and_then(Value::as_str))
+        .map(str::to_string)
+        .collect()
+}
+
+/// 64-bit FNV-1a. Fields are length-prefixed so adjacent ones can't run together.
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in (bytes.len() as u64).to_le_bytes().iter().chain(bytes) {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
@@ -0,0 +1,134 @@
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Flags rsh passes to every cargo invocation, from the `[cargo]` table of the
+/// config files:
+///
+/// ```toml
+/// [cargo]
+/// locked = true
+/// offline = true
+/// release = false
+/// features = ["serde"]
+/// ```
+///
+/// `--features` and `--release` on the command line override the table, and
+/// `color` follows the top-level `color` setting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CargoOptions {
+    /// Pass `--locked` (when the project has a `Cargo.lock`), so cargo fails instead
+    /// of updating a stale lock file.
+    pub locked: bool,
+    /// Pass `--offline`, so cargo never reaches the network.
+    pub offline: bool,
+    /// Features of the project to enable, passed as `--features`.
+    pub features: Vec<String>,
+    /// Build with `--release`.
+    pub release: bool,
+    /// Ask cargo and rustc for colored output (`--color=always`).
+    pub color: bool,
+}
+
+impl CargoOptions {
+    /// The cargo profile builds use.
+    pub fn profile(&self) -> &'static str {
+        if self.release {
+            "release"
+        } else {
+            "dev"
+        }
+    }
+}
+
+impl Default for CargoOptions {
+    fn default() -> Self {
+        CargoOptions {
+            locked: true,
+            offline: true,
+            features: Vec::new(),
+            release: false,
+            color: false,
+        }
+    }
+}
+
+/// The cargo to run: `$CARGO` if set (as it is under `cargo run` and in build
+/// scripts), otherwise `cargo` from `PATH`.
+pub fn cargo_program() -> OsString {
+    std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into())
+}
+
+/// `cargo <subcommand>` for the project in `project_dir`, with the flags from
+/// `options`. `--locked` is only added when `project_dir` has a lock file, since
+/// cargo would otherwise refuse to create one, and `--release` only to builds.
+pub fn cargo_command<P: AsRef<Path>>(subcommand: &str, project_dir: P, options: &CargoOptions) -> Command {
+    let project_dir = project_dir.as_ref();
+    let mut cmd = Command::new(cargo_program());
+    cmd.arg(subcommand)
+        .arg("--manifest-path")
+        .arg(project_dir.join("Cargo.toml"))
+        .current_dir(project_dir);
+    if options.locked && project_dir.join("Cargo.lock").exists() {
+        cmd.arg("--locked");
+    }
+    if options.offline {
+        cmd.arg("--offline");
+    }
+    if !options.features.is_empty() {
+        cmd.arg("--features").arg(options.features.join(","));
+    }
+    if options.color {
+        cmd.arg("--color=always");
+    }
+    if options.release && subcommand == "build" {
+        cmd.arg("--release");
+    }
+    cmd
+}
+
+/// The bytes of the project's `Cargo.toml` and `Cargo.lock` before a cargo
+/// invocation, to verify afterwards that cargo left them alone.
+pub struct ProjectFiles {
+    files: Vec<(PathBuf, Option<Vec<u8>>)>,
+}
+
+impl ProjectFiles {
+    pub fn snapshot<P: AsRef<Path>>(project_dir: P) -> Self {
+        let files = ["Cargo.toml", "Cargo.lock"]
+            .iter()
+            .map(|name| {
+                let path = project_dir.as_ref().join(name);
+                let bytes = fs::read(&path).ok();
+                (path, bytes)
+            })
+            .collect();
+        ProjectFiles { files }
+    }
+
+    /// Describe every file that differs from the snapshot.
+    pub fn changes(&self) -> Vec<String> {
+        let mut changes = Vec::new();
+        for (path, before) in &self.files {
+            let after = fs::read(path).ok();
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            match (before, &after) {
+                (None, Some(_)) => changes.push(format!("{name} was created")),
+                (Some(_), None) => changes.push(format!("{name} was removed")),
+                (Some(before), Some(after)) if before != after => changes.push(format!("{name} was modified")),
+                _ => {}
+            }
+        }
+        changes
+    }
+
+    /// Warn about every file cargo changed; returns whether anything did.
+    pub fn report(&self, during: &str) -> bool {
+        let changes = self.changes();
+        for change in &changes {
+            eprintln!("rsh: warning: {change} during {during}.");
+        }
+        !changes.is_empty()
+    }
+}
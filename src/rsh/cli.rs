@@ -0,0 +1,126 @@
+use std::path::PathBuf;
+
+use super::session::ModeRequest;
+
+pub const USAGE: &str = "Usage: rsh [options]\n       rsh [options] run <script.rsh|-> [--keep-going]\nRun `rsh --help` for the options.";
+
+pub const HELP: &str = "\
+rsh: a Rust REPL that runs each block as a binary of the current Cargo project.
+
+Usage: rsh [options]
+       rsh [options] run <script.rsh|->
+
+Options:
+  --project <dir>        Use the Cargo project in <dir> instead of the current directory
+  --standalone           Use a throwaway project, for running outside any Cargo project
+  --mode <mode>          Start in `sync`, `async` or `async <runtime> [flavor]`
+  --runtime <name>       Start in async mode under <name> (`tokio` or `tokio current_thread`)
+  --features <list>      Build with these features of the project (comma or space separated)
+  --release              Build in release mode
+  --resume               Restore the session last autosaved in the project
+  --load <file>          Run a script into the session before the prompt (repeatable)
+  --exec <code>          Run <code> and exit instead of starting the prompt (repeatable)
+  --keep-going           In scripts and --exec, keep running after a failed block
+  --history-file <file>  Load and save the prompt history in <file>
+  --no-cleanup           Leave the generated files (and a --standalone project) in place on exit
+  --version              Print the version and exit
+  -h, --help             Print this help and exit";
+
+/// Options given on the `rsh` command line.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Options {
+    /// Project directory; the current directory if unset.
+    pub project: Option<PathBuf>,
+    /// Mode to start in, overriding the project's default. Also set by `--runtime`.
+    pub mode: Option<ModeRequest>,
+    /// Run in a throwaway project instead of the current directory's.
+    pub standalone: bool,
+    /// Restore the project's last autosaved session.
+    pub resume: bool,
+    /// Features of the project to build with.
+    pub features: Vec<String>,
+    pub release: bool,
+    /// Scripts run into the session before the prompt.
+    pub load: Vec<PathBuf>,
+    /// Code run instead of the prompt, one script per `--exec`.
+    pub exec: Vec<String>,
+    /// Script given by `rsh run <script>`; `-` reads it from stdin.
+    pub script: Option<PathBuf>,
+    /// In batch mode, run the rest of the script after a failure.
+    pub keep_going: bool,
+    pub history_file: Option<PathBuf>,
+    /// Leave generated files behind on exit.
+    pub no_cleanup: bool,
+    pub help: bool,
+    pub version: bool,
+}
+
+/// Parse command-line arguments (without the program name).
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = args.into_iter();
+    let mut mode_flag: Option<&str> = None;
+
+    while let Some(arg) = args.next() {
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+            _ => (arg.clone(), None),
+        };
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("{} requires a value", flag))
+        };
+
+        match flag.as_str() {
+            "--project" => options.project = Some(PathBuf::from(value()?)),
+            "--mode" | "--runtime" => {
+                let given = value()?;
+                let mut words: Vec<&str> = given.split_whitespace().collect();
+                if flag == "--runtime" {
+                    if words.is_empty() || words.len() > 2 {
+                        return Err(format!("invalid --runtime '{}': expected '<name> [flavor]'", given));
+                    }
+                    words.insert(0, "async");
+                }
+                if let Some(previous) = mode_flag.filter(|previous| *previous != flag) {
+                    return Err(format!("{} and {} can't be combined", previous, flag));
+                }
+                mode_flag = Some(if flag == "--mode" { "--mode" } else { "--runtime" });
+                options.mode = Some(ModeRequest::parse(&words).map_err(|e| format!("invalid {} '{}': {}", flag, given, e))?);
+            }
+            "--features" => {
+                let list = value()?;
+                options.features.extend(
+                    list.split(|c: char| c == ',' || c.is_whitespace())
+                        .filter(|feature| !feature.is_empty())
+                        .map(str::to_string),
+                );
+            }
+            "--release" => options.release = true,
+            "--standalone" => options.standalone = true,
+            "--resume" => options.resume = true,
+            "--load" => options.load.push(PathBuf::from(value()?)),
+            "--exec" => options.exec.push(value()?),
+            "--keep-going" => options.keep_going = true,
+            "--history-file" => options.history_file = Some(PathBuf::from(value()?)),
+            "--no-cleanup" => options.no_cleanup = true,
+            "--version" | "-V" => options.version = true,
+            "--help" | "-h" => options.help = true,
+            "run" if options.script.is_none() => {
+                let script = args.next().ok_or("run requires a script (or - for stdin)")?;
+                options.script = Some(PathBuf::from(script));
+            }
+            other => return Err(format!("unknown argument '{}'", other)),
+        }
+    }
+
+    if options.standalone && options.project.is_some() {
+        return Err("--standalone and --project can't be combined".to_string());
+    }
+    if options.script.is_some() && !options.exec.is_empty() {
+        return Err("run and --exec can't be combined".to_string());
+    }
+    Ok(options)
+}
@@ -0,0 +1,409 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+
+use toml::{Table, Value};
+
+use super::cargo::CargoOptions;
+use super::session::{FailurePolicy, ModeRequest, Warnings};
+use super::split::Layout;
+use super::template::Template;
+
+/// Name of the per-project configuration file, looked up in the project root.
+pub const PROJECT_CONFIG_FILE: &str = ".rsh.toml";
+
+/// The user's configuration file: `$XDG_CONFIG_HOME/rsh/config.toml`, or
+/// `~/.config/rsh/config.toml`. `None` if neither variable is set.
+pub fn user_config_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(base.join("rsh").join("config.toml"))
+}
+
+/// An async runtime declared in the config file:
+///
+/// ```toml
+/// [[runtime]]
+/// name = "my-executor"
+/// package = "my-executor"
+/// flavors = ["fast", "slow"]
+/// main = """
+/// fn main() {
+///     {crate}::run_{flavor}(async {
+///         {session}
+///     });
+/// }
+/// """
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserRuntime {
+    pub name: String,
+    /// Dependency that provides the runtime; defaults to `name`.
+    pub package: String,
+    pub flavors: Vec<String>,
+    pub main: String,
+}
+
+/// When rsh colors the prompt and compiler diagnostics: `color = "auto"`
+/// (when stderr is a terminal), `"always"` or `"never"`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorChoice {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "auto" => Some(ColorChoice::Auto),
+            "always" => Some(ColorChoice::Always),
+            "never" => Some(ColorChoice::Never),
+            _ => None,
+        }
+    }
+
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Auto => io::stderr().is_terminal(),
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+impl fmt::Display for ColorChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ColorChoice::Auto => write!(f, "auto"),
+            ColorChoice::Always => write!(f, "always"),
+            ColorChoice::Never => write!(f, "never"),
+        }
+    }
+}
+
+/// Settings read from the user's config file and the project's `.rsh.toml`,
+/// the project's taking precedence key by key:
+///
+/// ```toml
+/// mode = "async tokio"
+/// layout = "single"
+/// runtime-order = ["smol", "tokio"]
+/// prelude = ["use std::collections::HashMap;"]
+/// preamble-prefixes = ["macro_rules!"]
+/// async-patterns = ["block_on!("]
+/// color = "auto"
+/// warnings = "default"
+/// prompt = "rsh{status} [{mode}]> "
+///
+/// [on-failure]
+/// compile = "keep"
+/// runtime = "ask"
+///
+/// [cargo]
+/// features = ["serde"]
+///
+/// [aliases]
+/// s = ":show"
+///
+/// [templates]
+/// sync = """
+/// {preamble}
+/// fn __rsh_session() -> {error_type} {
+///     {init}
+///     {body}
+///     Ok(())
+/// }
+///
+/// {main}
+/// """
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Config {
+    /// Mode a session starts in, written like the `:mode` arguments:
+    /// `mode = "sync"`, `mode = "async"` or `mode = "async tokio current_thread"`.
+    pub mode: Option<ModeRequest>,
+    /// Build layout, `layout = "single"` or `layout = "split"`.
+    pub layout: Option<Layout>,
+    pub runtimes: Vec<UserRuntime>,
+    /// Runtimes the async auto-switch tries first, in this order.
+    pub runtime_order: Vec<String>,
+    /// Lines put at module scope of every generated program, ahead of the PREAMBLE.
+    /// `None` uses the default prelude: `use <host crate>::*;` for a project with a library.
+    pub prelude: Option<Vec<String>>,
+    /// Extra line prefixes that start a PREAMBLE item, besides `use`, `fn`, `struct`, ….
+    pub preamble_prefixes: Vec<String>,
+    /// Extra text that marks a BODY as async, besides `.await` and `async` blocks.
+    pub async_patterns: Vec<String>,
+    /// Initial `:on-failure` policies, from the `[on-failure]` table.
+    pub on_compile_failure: Option<FailurePolicy>,
+    pub on_runtime_failure: Option<FailurePolicy>,
+    /// Flags for cargo invocations, from the `[cargo]` table.
+    pub cargo: CargoOptions,
+    pub color: ColorChoice,
+    /// Initial `:warnings` setting.
+    pub warnings: Warnings,
+    /// Prompt format; `{status}`, `{mode}` and `{target}` are filled in.
+    pub prompt: Option<String>,
+    /// Command aliases from the `[aliases]` table: `:name args` runs `<command> args`.
+    pub aliases: BTreeMap<String, String>,
+    /// Code generation templates from the `[templates]` table, replacing
+    /// [`Template::sync`] and [`Template::async_`].
+    pub sync_template: Option<Template>,
+    pub async_template: Option<Template>,
+    /// The file each key was set in, by dotted key (`mode`, `cargo.locked`,
+    /// `aliases.s`, `runtime.<name>`). Keys at their default are absent.
+    pub sources: BTreeMap<String, PathBuf>,
+}
+
+/// Default prompt format.
+pub const DEFAULT_PROMPT: &str = "rsh{status}> ";
+
+/// Tables whose keys are merged one by one across config files.
+const MERGED_TABLES: [&str; 4] = ["cargo", "on-failure", "aliases", "templates"];
+
+impl Config {
+    /// Load the user's config file, then `.rsh.toml` from the project root on top
+    /// of it. Missing files are empty configs.
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<Self, Box<dyn Error>> {
+        let layers: Vec<PathBuf> = [user_config_path(), Some(dir.as_ref().join(PROJECT_CONFIG_FILE))]
+            .into_iter()
+            .flatten()
+            .collect();
+        Config::load_layers(&layers)
+    }
+
+    /// Load config files in order, each overriding the ones before it key by key.
+    pub fn load_layers(layers: &[PathBuf]) -> Result<Self, Box<dyn Error>> {
+        let mut merged = Table::new();
+        let mut sources = BTreeMap::new();
+        for path in layers.iter().filter(|path| path.is_file()) {
+            let text = fs::read_to_string(path)?;
+            let layer: Table = text.parse().map_err(|e| format!("{}: {}", path.display(), e))?;
+            // Check each file on its own, so errors name the file they are in.
+            Config::from_table(&layer).map_err(|e| format!("{}: {}", path.display(), e))?;
+            merge(&mut merged, layer, path, &mut sources);
+        }
+        let mut config = Config::from_table(&merged)?;
+        config.sources = sources;
+        Ok(config)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        Config::from_table(&text.parse()?)
+    }
+
+    fn from_table(table: &Table) -> Result<Self, Box<dyn Error>> {
+        let mut config = Config::default();
+        for (key, value) in table {
+            match key.as_str() {
+                "mode" => {
+                    let mode = value.as_str().ok_or("`mode` must be a string")?;
+                    let words: Vec<&str> = mode.split_whitespace().collect();
+                    config.mode = Some(ModeRequest::parse(&words).map_err(|e| format!("invalid `mode`: {}", e))?);
+                }
+                "layout" => {
+                    let layout = value.as_str().ok_or("`layout` must be a string")?;
+                    config.layout = Some(Layout::parse(layout).ok_or_else(|| format!("invalid `layout`: {layout}"))?);
+                }
+                "runtime" => {
+                    let runtimes = value.as_array().ok_or("`runtime` must be an array of tables ([[runtime]])")?;
+                    for runtime in runtimes {
+                        let runtime = parse_runtime(runtime)?;
+                        // The same name in a later config file comes first and wins.
+                        if !config.runtimes.iter().any(|r| r.name == runtime.name) {
+                            config.runtimes.push(runtime);
+                        }
+                    }
+                }
+                "runtime-order" => config.runtime_order = strings(key, value)?,
+                "prelude" => config.prelude = Some(strings(key, value)?),
+                "preamble-prefixes" => config.preamble_prefixes = strings(key, value)?,
+                "async-patterns" => config.async_patterns = strings(key, value)?,
+                "on-failure" => {
+                    let policies = value.as_table().ok_or("`on-failure` must be a table ([on-failure])")?;
+                    for (kind, policy) in policies {
+                        let parsed = policy
+                            .as_str()
+                            .and_then(FailurePolicy::parse)
+                            .ok_or_else(|| format!("`on-failure.{kind}` must be \"rollback\", \"keep\" or \"ask\""))?;
+                        match kind.as_str() {
+                            "compile" => config.on_compile_failure = Some(parsed),
+                            "runtime" => config.on_runtime_failure = Some(parsed),
+                            _ => return Err(format!("unknown key `on-failure.{kind}`").into()),
+                        }
+                    }
+                }
+                "cargo" => {
+                    let cargo = value.as_table().ok_or("`cargo` must be a table ([cargo])")?;
+                    for (key, value) in cargo {
+                        let flag = || value.as_bool().ok_or_else(|| format!("`cargo.{key}` must be true or false"));
+                        match key.as_str() {
+                            "locked" => config.cargo.locked = flag()?,
+                            "offline" => config.cargo.offline = flag()?,
+                            "release" => config.cargo.release = flag()?,
+                            "features" => config.cargo.features = strings("cargo.features", value)?,
+                            _ => return Err(format!("unknown key `cargo.{key}`").into()),
+                        }
+                    }
+                }
+                "color" => {
+                    let color = value.as_str().ok_or("`color` must be a string")?;
+                    config.color = ColorChoice::parse(color)
+                        .ok_or_else(|| format!("invalid `color`: {color}; expected auto, always or never"))?;
+                }
+                "warnings" => {
+                    let warnings = value.as_str().ok_or("`warnings` must be a string")?;
+                    config.warnings = Warnings::parse(warnings)
+                        .ok_or_else(|| format!("invalid `warnings`: {warnings}; expected default, on, off or deny"))?;
+                }
+                "prompt" => config.prompt = Some(value.as_str().ok_or("`prompt` must be a string")?.to_string()),
+                "aliases" => {
+                    let aliases = value.as_table().ok_or("`aliases` must be a table ([aliases])")?;
+                    for (name, command) in aliases {
+                        let command = command.as_str().ok_or_else(|| format!("`aliases.{name}` must be a string"))?;
+                        if !command.starts_with(':') {
+                            return Err(format!("`aliases.{name}` must be a meta-command starting with ':'").into());
+                        }
+                        config.aliases.insert(name.trim_start_matches(':').to_string(), command.to_string());
+                    }
+                }
+                "templates" => {
+                    let templates = value.as_table().ok_or("`templates` must be a table ([templates])")?;
+                    for (mode, text) in templates {
+                        let text = text.as_str().ok_or_else(|| format!("`templates.{mode}` must be a string"))?;
+                        let template = Template::parse(text).map_err(|e| format!("invalid `templates.{mode}`: {e}"))?;
+                        match mode.as_str() {
+                            "sync" => config.sync_template = Some(template),
+                            "async" => config.async_template = Some(template),
+                            _ => return Err(format!("unknown key `templates.{mode}`").into()),
+                        }
+                    }
+                }
+                _ => return Err(format!("unknown key `{key}`").into()),
+            }
+        }
+        Ok(config)
+    }
+
+    /// The file `key` was set in, or `None` for a default.
+    pub fn source(&self, key: &str) -> Option<&Path> {
+        self.sources.get(key).map(PathBuf::as_path)
+    }
+
+    /// Every setting as `(dotted key, value)`, values written as in the config file.
+    pub fn entries(&self) -> Vec<(String, String)> {
+        let list = |items: &[String]| format!("{:?}", items);
+        let policy = |policy: Option<FailurePolicy>| format!("\"{}\"", policy.unwrap_or(FailurePolicy::Rollback));
+
+        let mut entries = vec![
+            ("mode".to_string(), format!("\"{}\"", self.mode.as_ref().unwrap_or(&ModeRequest::Sync))),
+            ("layout".to_string(), format!("\"{}\"", self.layout.unwrap_or(Layout::Single))),
+            ("runtime-order".to_string(), list(&self.runtime_order)),
+            (
+                "prelude".to_string(),
+                self.prelude.as_deref().map_or_else(|| "<use the host crate's library>".to_string(), list),
+            ),
+            ("preamble-prefixes".to_string(), list(&self.preamble_prefixes)),
+            ("async-patterns".to_string(), list(&self.async_patterns)),
+            ("color".to_string(), format!("\"{}\"", self.color)),
+            ("warnings".to_string(), format!("\"{}\"", self.warnings)),
+            ("prompt".to_string(), format!("{:?}", self.prompt.as_deref().unwrap_or(DEFAULT_PROMPT))),
+            ("on-failure.compile".to_string(), policy(self.on_compile_failure)),
+            ("on-failure.runtime".to_string(), policy(self.on_runtime_failure)),
+            ("cargo.locked".to_string(), self.cargo.locked.to_string()),
+            ("cargo.offline".to_string(), self.cargo.offline.to_string()),
+            ("cargo.release".to_string(), self.cargo.release.to_string()),
+            ("cargo.features".to_string(), list(&self.cargo.features)),
+        ];
+        let template = |template: &Option<Template>| match template {
+            Some(template) => format!("\"\"\"\n{template}\"\"\""),
+            None => "<built in>".to_string(),
+        };
+        entries.push(("templates.sync".to_string(), template(&self.sync_template)));
+        entries.push(("templates.async".to_string(), template(&self.async_template)));
+        for (name, command) in &self.aliases {
+            entries.push((format!("aliases.{name}"), format!("{command:?}")));
+        }
+        for runtime in &self.runtimes {
+            entries.push((format!("runtime.{}", runtime.name), format!("package {:?}", runtime.package)));
+        }
+        entries
+    }
+
+    /// Print the effective settings and where each came from, for `:config`.
+    pub fn print(&self) {
+        for (key, value) in self.entries() {
+            let source = self
+                .source(&key)
+                .map(|path| path.display().to_string())
+                .unwrap_or_else(|| "default".to_string());
+            println!("{key} = {value}  # {source}");
+        }
+    }
+}
+
+/// Merge a config file's table into `into`, recording `source` for each key it sets.
+fn merge(into: &mut Table, layer: Table, source: &Path, sources: &mut BTreeMap<String, PathBuf>) {
+    for (key, value) in layer {
+        match value {
+            Value::Array(mut runtimes) if key == "runtime" => {
+                for name in runtimes.iter().filter_map(|r| r.get("name").and_then(Value::as_str)) {
+                    sources.insert(format!("runtime.{name}"), source.to_path_buf());
+                }
+                // Later files come first, so their runtimes win on a name clash.
+                if let Some(Value::Array(earlier)) = into.remove("runtime") {
+                    runtimes.extend(earlier);
+                }
+                into.insert(key, Value::Array(runtimes));
+            }
+            Value::Table(table) if MERGED_TABLES.contains(&key.as_str()) => {
+                let merged = into.entry(key.clone()).or_insert_with(|| Value::Table(Table::new()));
+                if let Value::Table(merged) = merged {
+                    for (inner, value) in table {
+                        sources.insert(format!("{key}.{inner}"), source.to_path_buf());
+                        merged.insert(inner, value);
+                    }
+                }
+            }
+            value => {
+                sources.insert(key.clone(), source.to_path_buf());
+                into.insert(key, value);
+            }
+        }
+    }
+}
+
+fn strings(key: &str, value: &Value) -> Result<Vec<String>, String> {
+    value
+        .as_array()
+        .and_then(|items| items.iter().map(|v| v.as_str().map(str::to_string)).collect())
+        .ok_or_else(|| format!("`{key}` must be an array of strings"))
+}
+
+fn parse_runtime(value: &Value) -> Result<UserRuntime, Box<dyn Error>> {
+    let table = value.as_table().ok_or("each [[runtime]] entry must be a table")?;
+    let string = |key: &str| table.get(key).and_then(Value::as_str).map(str::to_string);
+
+    let name = string("name").ok_or("[[runtime]] entry is missing `name`")?;
+    let main = string("main").ok_or_else(|| format!("runtime `{}` is missing `main`", name))?;
+    let package = string("package").unwrap_or_else(|| name.clone());
+    let flavors = table
+        .get("flavors")
+        .and_then(Value::as_array)
+        .map(|f| f.iter().filter_map(Value::as_str).map(str::to_string).collect())
+        .unwrap_or_default();
+
+    Ok(UserRuntime {
+        name,
+        package,
+        flavors,
+        main,
+    })
+}
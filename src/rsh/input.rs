@@ -1,17 +1,22 @@
+use std::error::Error;
+
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 use rustyline::history::DefaultHistory;
 
-use super::session::{Segment, Session};
+use super::session::{FailureKind, FailurePolicy, Mode, ModeRequest, Segment, Session, Warnings};
+use super::split::Layout;
 
 pub enum Input {
     Command(String),
     Code(String),
 }
 
-pub fn read_block(rl: &mut Editor<(), DefaultHistory>) -> Result<Option<Input>, ReadlineError> {
+/// Read one block; `first_prompt` is shown for its first line (`rsh> `, or with a
+/// status indicator), `...> ` for continuation lines.
+pub fn read_block(rl: &mut Editor<(), DefaultHistory>, first_prompt: &str) -> Result<Option<Input>, ReadlineError> {
     let mut block: Vec<String> = Vec::new();
-    let mut prompt = "rsh> ";
+    let mut prompt = first_prompt;
 
     loop {
         let line = rl.readline(prompt)?;
@@ -19,7 +24,13 @@ pub fn read_block(rl: &mut Editor<(), DefaultHistory>) -> Result<Option<Input>,
 
         // Meta-commands: only recognized when starting a new block.
         if block.is_empty() && trimmed.starts_with(':') {
-            let cmd = trimmed.to_string();
+            let mut cmd = trimmed.to_string();
+            // `:check {` takes lines until its braces close, blank ones included.
+            while command_continues(&cmd) {
+                let line = rl.readline("...> ")?;
+                cmd.push('\n');
+                cmd.push_str(&line);
+            }
             return Ok(Some(Input::Command(cmd)));
         }
 
@@ -27,7 +38,7 @@ pub fn read_block(rl: &mut Editor<(), DefaultHistory>) -> Result<Option<Input>,
         if trimmed.is_empty() {
             if block.is_empty() {
                 // Ignore stray empty lines.
-                prompt = "rsh> ";
+                prompt = first_prompt;
                 continue;
             } else {
                 // End of multi-line block.
@@ -37,33 +48,136 @@ pub fn read_block(rl: &mut Editor<(), DefaultHistory>) -> Result<Option<Input>,
         }
 
         // inside read_block, where you currently call add_history_entry
-        if let Err(e) = rl.add_history_entry(line.as_str()) {
         // internal failure: bubble up so main can print and exit
-            return Err(e);
-        }
+        rl.add_history_entry(line.as_str())?;
         block.push(line);
         prompt = "...> ";
     }
 }
 
+/// Whether a meta-command goes on to the next line: a `:check { ... }` block
+/// whose braces aren't closed yet.
+pub fn command_continues(cmd: &str) -> bool {
+    cmd.starts_with(":check") && count_opening_braces(cmd) > count_closing_braces(cmd)
+}
+
+/// What became of a meta-command passed to [`handle_command`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandResult {
+    Handled,
+    /// `:q` / `:quit`.
+    Quit,
+    /// Not a known command; an error was printed.
+    Unknown,
+    /// The command was understood but failed (bad arguments, a missing file,
+    /// a `:check` that doesn't compile...); an error was printed.
+    Failed,
+}
+
+/// Dispatch a meta-command to its handler. Shared by the REPL and batch mode;
+/// `Err` is an internal error that ends the session.
+pub fn handle_command(cmd: &str, session: &mut Session) -> Result<CommandResult, Box<dyn Error>> {
+    let expanded = expand_alias(cmd, session);
+    let cmd = expanded.as_str();
+    let head = cmd.split_whitespace().next().unwrap_or("");
+    let result = match head {
+        ":delete" => handle_delete_command(cmd, session),
+        ":on-failure" => handle_on_failure_command(cmd, session),
+        ":layout" => handle_layout_command(cmd, session),
+        ":prelude" => handle_prelude_command(cmd, session),
+        ":log" => handle_log_command(cmd, session),
+        ":warnings" => handle_warnings_command(cmd, session),
+        ":check" => handle_check_command(cmd, session)?,
+        ":session" | ":resume" => handle_session_command(cmd, session),
+        ":env" | ":unenv" | ":args" | ":cwd" | ":stdin" => handle_run_setting_command(cmd, session),
+        ":runtime" => handle_runtime_command(cmd, session)?,
+        ":mode" => handle_mode_command(cmd, session)?,
+        _ => match cmd {
+            ":q" | ":quit" => CommandResult::Quit,
+            ":reset" => {
+                session.reset();
+                println!("Session reset.");
+                CommandResult::Handled
+            }
+            ":config" => {
+                session.config().print();
+                CommandResult::Handled
+            }
+            ":show" => {
+                session.show();
+                CommandResult::Handled
+            }
+            ":show all" => {
+                session.show_all();
+                CommandResult::Handled
+            }
+            ":stats" => {
+                session.stats().print();
+                CommandResult::Handled
+            }
+            ":clippy" => run_clippy(session),
+            ":fmt" => match session.format() {
+                Ok(true) => {
+                    println!("Formatted the session.");
+                    session.show();
+                    CommandResult::Handled
+                }
+                Ok(false) => {
+                    println!("The session is already formatted.");
+                    CommandResult::Handled
+                }
+                Err(e) => {
+                    eprintln!("rsh: rustfmt: {e}");
+                    CommandResult::Failed
+                }
+            },
+            ":sessions" => {
+                session.show_sessions();
+                CommandResult::Handled
+            }
+            _ => {
+                eprintln!("Unknown command: {cmd}");
+                CommandResult::Unknown
+            }
+        },
+    };
+    Ok(result)
+}
+
+/// Replace an alias from the config's `[aliases]` table with its command,
+/// keeping the arguments: with `s = ":show"`, `:s` runs `:show`.
+fn expand_alias(cmd: &str, session: &Session) -> String {
+    let (head, rest) = match cmd.split_once(char::is_whitespace) {
+        Some((head, rest)) => (head, Some(rest.trim())),
+        None => (cmd, None),
+    };
+    let Some(command) = head.strip_prefix(':').and_then(|name| session.config().aliases.get(name)) else {
+        return cmd.to_string();
+    };
+    match rest {
+        Some(rest) if !rest.is_empty() => format!("{command} {rest}"),
+        _ => command.clone(),
+    }
+}
+
 /// Handle the `:delete` meta-command.
 ///
 /// Expected syntax:
 /// `:delete <preamble|body> <index...>`
-pub fn handle_delete_command(cmd: &str, session: &mut Session) {
+pub fn handle_delete_command(cmd: &str, session: &mut Session) -> CommandResult {
     let mut parts = cmd.split_whitespace();
     let head = parts.next().unwrap_or("");
 
     if head != ":delete" {
         eprintln!("rsh: internal error: handle_delete_command called with non-:delete command");
-        return;
+        return CommandResult::Failed;
     }
 
     let target_str = match parts.next() {
         Some(s) => s,
         None => {
             eprintln!("Usage: :delete <preamble|body> <index...>");
-            return;
+            return CommandResult::Failed;
         }
     };
 
@@ -72,7 +186,7 @@ pub fn handle_delete_command(cmd: &str, session: &mut Session) {
         "body" => Segment::Body,
         other => {
             eprintln!("Invalid segment '{}'; expected 'preamble' or 'body'.", other);
-            return;
+            return CommandResult::Failed;
         }
     };
 
@@ -83,19 +197,681 @@ pub fn handle_delete_command(cmd: &str, session: &mut Session) {
             Ok(i) => indices.push(i),
             Err(_) => {
                 eprintln!("Invalid index: '{}'", p);
-                return;
+                return CommandResult::Failed;
             }
         }
     }
 
     if indices.is_empty() {
         eprintln!("Usage: :delete <preamble|body> <index...>");
-        return;
+        return CommandResult::Failed;
     }
 
-    session.delete(segment, &indices);
+    if session.delete(segment, &indices) {
+        CommandResult::Handled
+    } else {
+        CommandResult::Failed
+    }
 }
 
+/// Handle the `:on-failure` meta-command.
+///
+/// Expected syntax:
+/// `:on-failure [compile|runtime] <rollback|keep|ask>`; without a kind both are set,
+/// and `:on-failure` alone prints the current policies.
+pub fn handle_on_failure_command(cmd: &str, session: &mut Session) -> CommandResult {
+    const USAGE: &str = "Usage: :on-failure [compile|runtime] <rollback|keep|ask>";
+    let words: Vec<&str> = cmd.split_whitespace().skip(1).collect();
+
+    let (kinds, policy) = match words.as_slice() {
+        [] => {
+            println!(
+                "compile: {}\nruntime: {}",
+                session.failure_policy(FailureKind::Compile),
+                session.failure_policy(FailureKind::Runtime)
+            );
+            return CommandResult::Handled;
+        }
+        [policy] => (vec![FailureKind::Compile, FailureKind::Runtime], *policy),
+        ["compile", policy] => (vec![FailureKind::Compile], *policy),
+        ["runtime", policy] => (vec![FailureKind::Runtime], *policy),
+        _ => {
+            eprintln!("{USAGE}");
+            return CommandResult::Failed;
+        }
+    };
+
+    let Some(policy) = FailurePolicy::parse(policy) else {
+        eprintln!("Invalid policy '{}'; expected 'rollback', 'keep' or 'ask'.", policy);
+        return CommandResult::Failed;
+    };
+    for kind in kinds {
+        session.set_failure_policy(kind, policy);
+    }
+    CommandResult::Handled
+}
+
+/// Handle the `:layout` meta-command.
+///
+/// Expected syntax:
+/// `:layout [single|split]`; `:layout` alone prints the current layout.
+pub fn handle_layout_command(cmd: &str, session: &mut Session) -> CommandResult {
+    let words: Vec<&str> = cmd.split_whitespace().skip(1).collect();
+    match words.as_slice() {
+        [] => println!("{}", session.layout()),
+        [layout] => match Layout::parse(layout) {
+            Some(layout) => {
+                session.set_layout(layout);
+                println!("Layout: {layout}");
+            }
+            None => {
+                eprintln!("Invalid layout '{}'; expected 'single' or 'split'.", layout);
+                return CommandResult::Failed;
+            }
+        },
+        _ => {
+            eprintln!("Usage: :layout [single|split]");
+            return CommandResult::Failed;
+        }
+    }
+    CommandResult::Handled
+}
+
+/// Handle the `:prelude` meta-command.
+///
+/// Expected syntax:
+/// `:prelude add <line>`, `:prelude remove <index>`; `:prelude` alone lists the lines.
+pub fn handle_prelude_command(cmd: &str, session: &mut Session) -> CommandResult {
+    const USAGE: &str = "Usage: :prelude [add <line>|remove <index>]";
+    let rest = cmd.strip_prefix(":prelude").unwrap_or("").trim();
+    let (action, arg) = match rest.split_once(char::is_whitespace) {
+        Some((action, arg)) => (action, arg.trim()),
+        None => (rest, ""),
+    };
+
+    match (action, arg) {
+        ("", _) => {
+            if session.prelude().is_empty() {
+                println!("<empty>");
+            }
+            for (index, line) in session.prelude().iter().enumerate() {
+                println!("[{}] {}", index, line);
+            }
+        }
+        ("add", line) if !line.is_empty() => match session.add_prelude(line) {
+            Ok(()) => println!("Added to the prelude."),
+            Err(e) => {
+                eprintln!("rsh: {e}");
+                return CommandResult::Failed;
+            }
+        },
+        ("remove", index) if !index.is_empty() => match index.parse::<usize>() {
+            Ok(index) => match session.remove_prelude(index) {
+                Ok(line) => println!("Removed '{line}' from the prelude."),
+                Err(e) => {
+                    eprintln!("rsh: {e}");
+                    return CommandResult::Failed;
+                }
+            },
+            Err(_) => {
+                eprintln!("Invalid index: '{}'", index);
+                return CommandResult::Failed;
+            }
+        },
+        _ => {
+            eprintln!("{USAGE}");
+            return CommandResult::Failed;
+        }
+    }
+    CommandResult::Handled
+}
+
+/// `:clippy`: print the lints on session lines, each with the line it's on.
+fn run_clippy(session: &mut Session) -> CommandResult {
+    let lints = match session.clippy() {
+        Ok(lints) => lints,
+        Err(e) => {
+            eprintln!("rsh: clippy: {e}");
+            return CommandResult::Failed;
+        }
+    };
+    for lint in &lints {
+        let (name, lines) = match lint.segment {
+            Segment::Preamble => ("PREAMBLE", session.preamble()),
+            Segment::Body => ("BODY", session.body()),
+        };
+        let line = lines.get(lint.index).map(String::as_str).unwrap_or("");
+        eprintln!("--- {} [{}] {}", name, lint.index, line);
+        eprint!("{}", lint.diagnostic.rendered);
+    }
+    match lints.len() {
+        0 => println!("No clippy lints on session lines."),
+        1 => println!("1 clippy lint on session lines."),
+        n => println!("{n} clippy lints on session lines."),
+    }
+    CommandResult::Handled
+}
+
+/// Handle the `:check` meta-command.
+///
+/// Expected syntax:
+/// `:check` type-checks the session; `:check { <block> }` (over several lines
+/// if needed) type-checks it with the block added, without keeping it.
+pub fn handle_check_command(cmd: &str, session: &mut Session) -> Result<CommandResult, Box<dyn Error>> {
+    let rest = cmd.strip_prefix(":check").unwrap_or("").trim();
+    if rest.is_empty() {
+        return Ok(checked(session.check(None)?));
+    }
+    let Some(inner) = rest.strip_prefix('{').and_then(|rest| rest.strip_suffix('}')) else {
+        eprintln!("Usage: :check [{{ <block> }}]");
+        return Ok(CommandResult::Failed);
+    };
+    let block: Vec<&str> = inner.lines().filter(|line| !line.trim().is_empty()).collect();
+    if block.is_empty() {
+        eprintln!("rsh: :check {{ }} needs a block to check.");
+        return Ok(CommandResult::Failed);
+    }
+    Ok(checked(session.check(Some(&block.join("\n")))?))
+}
+
+fn checked(compiled: bool) -> CommandResult {
+    if compiled {
+        CommandResult::Handled
+    } else {
+        CommandResult::Failed
+    }
+}
+
+/// Handle the `:warnings` meta-command.
+///
+/// Expected syntax:
+/// `:warnings [default|on|off|deny]`; `:warnings` alone prints the current setting.
+pub fn handle_warnings_command(cmd: &str, session: &mut Session) -> CommandResult {
+    let words: Vec<&str> = cmd.split_whitespace().skip(1).collect();
+    match words.as_slice() {
+        [] => println!("{}", session.warnings()),
+        [setting] => match Warnings::parse(setting) {
+            Some(warnings) => {
+                session.set_warnings(warnings);
+                println!("Warnings: {warnings}");
+            }
+            None => {
+                eprintln!("Invalid setting '{}'; expected 'default', 'on', 'off' or 'deny'.", setting);
+                return CommandResult::Failed;
+            }
+        },
+        _ => {
+            eprintln!("Usage: :warnings [default|on|off|deny]");
+            return CommandResult::Failed;
+        }
+    }
+    CommandResult::Handled
+}
+
+/// Handle the `:log` meta-command.
+///
+/// Expected syntax:
+/// `:log <level|filter>`, e.g. `:log debug` or `:log my_crate=trace,hyper=warn`;
+/// `:log` alone prints the logging crate and the current filter.
+pub fn handle_log_command(cmd: &str, session: &mut Session) -> CommandResult {
+    let filter = cmd.strip_prefix(":log").unwrap_or("").trim();
+    if filter.is_empty() {
+        match session.logger() {
+            Some(logger) => println!("Logging with {}, filter {}", logger, session.log_filter().unwrap_or_else(|| "<unset>".to_string())),
+            None => println!("No logging crate among the dependencies."),
+        }
+        return CommandResult::Handled;
+    }
+    if filter.contains(char::is_whitespace) {
+        eprintln!("Usage: :log <level|filter>");
+        return CommandResult::Failed;
+    }
+    session.set_log_filter(filter);
+    if session.logger().is_none() {
+        eprintln!("rsh: no logging crate among the dependencies; RUST_LOG is set, but nothing is initialized.");
+    }
+    CommandResult::Handled
+}
+
+/// Handle the `:session` and `:resume` meta-commands.
+///
+/// Expected syntax:
+/// `:session save <name>`, `:session open <name>`, `:session list`, `:resume`
+pub fn handle_session_command(cmd: &str, session: &mut Session) -> CommandResult {
+    const USAGE: &str = "Usage: :session <save <name>|open <name>|list>";
+    let words: Vec<&str> = cmd.split_whitespace().collect();
+
+    let result = match words.as_slice() {
+        [":resume"] => session.resume().map(|()| report_restored("Resumed the last session", session)),
+        [":session", "save", name] => session
+            .save_session(name)
+            .map(|path| println!("Saved session '{}' to {}.", name, path.display())),
+        [":session", "open", name] => session
+            .open_session(name)
+            .map(|()| report_restored(&format!("Opened session '{name}'"), session)),
+        [":session", "list"] => {
+            let saved = session.saved_sessions();
+            if saved.is_empty() {
+                println!("No saved sessions.");
+            }
+            for stored in saved {
+                let age = stored.modified.elapsed().unwrap_or_default().as_secs();
+                println!("{:<20}  saved {}", stored.name, format_age(age));
+            }
+            Ok(())
+        }
+        _ => {
+            eprintln!("{USAGE}");
+            return CommandResult::Failed;
+        }
+    };
+    match result {
+        Ok(()) => CommandResult::Handled,
+        Err(e) => {
+            eprintln!("rsh: {e}");
+            CommandResult::Failed
+        }
+    }
+}
+
+fn report_restored(what: &str, session: &Session) {
+    println!(
+        "{}: {} PREAMBLE and {} BODY lines, {} mode. They run with the next block.",
+        what,
+        session.preamble().len(),
+        session.body().len(),
+        session.mode()
+    );
+}
+
+fn format_age(secs: u64) -> String {
+    match secs {
+        0..=59 => "just now".to_string(),
+        60..=3599 => format!("{}m ago", secs / 60),
+        3600..=86399 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
+
+/// Ask what to do with a failed block under the `ask` policy.
+///
+/// Shows the block and reads `k`eep, `d`rop or `e`dit. Keep and drop resolve the
+/// failure; edit drops the block and returns the edited version, which the caller
+/// adds and runs like a freshly typed block.
+pub fn resolve_failed_block(
+    rl: &mut Editor<(), DefaultHistory>,
+    session: &mut Session,
+) -> Result<Option<String>, ReadlineError> {
+    let Some(block) = session.pending_failure().map(str::to_string) else {
+        return Ok(None);
+    };
+
+    eprintln!("--- FAILED BLOCK ---");
+    for line in block.lines() {
+        eprintln!("{line}");
+    }
+
+    loop {
+        let answer = rl.readline("[k]eep, [d]rop or [e]dit? ")?;
+        match answer.trim() {
+            "k" | "keep" => {
+                session.keep_last_block();
+                println!("Block kept.");
+                return Ok(None);
+            }
+            "d" | "drop" => {
+                session.drop_last_block();
+                println!("Block dropped.");
+                return Ok(None);
+            }
+            "e" | "edit" => {
+                session.drop_last_block();
+                return edit_block(rl, &block);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Re-read `block` line by line with each line pre-filled. Clearing a line removes
+/// it; further lines can be appended until a blank line ends the block.
+fn edit_block(rl: &mut Editor<(), DefaultHistory>, block: &str) -> Result<Option<String>, ReadlineError> {
+    let mut edited: Vec<String> = Vec::new();
+    for line in block.lines() {
+        let line = rl.readline_with_initial("edit> ", (line, ""))?;
+        if !line.trim().is_empty() {
+            edited.push(line);
+        }
+    }
+
+    loop {
+        let line = rl.readline("...> ")?;
+        if line.trim().is_empty() {
+            break;
+        }
+        edited.push(line);
+    }
+
+    if edited.is_empty() {
+        println!("Block dropped.");
+        return Ok(None);
+    }
+    let code = edited.join("\n");
+    rl.add_history_entry(code.as_str())?;
+    Ok(Some(code))
+}
+
+/// Handle the commands that control how the generated program is launched.
+///
+/// Expected syntax:
+/// `:env KEY=VALUE`, `:unenv KEY`, `:args [arg...]`, `:cwd [dir]`, `:stdin [file]`
+pub fn handle_run_setting_command(cmd: &str, session: &mut Session) -> CommandResult {
+    let (head, rest) = match cmd.split_once(char::is_whitespace) {
+        Some((head, rest)) => (head, rest.trim()),
+        None => (cmd, ""),
+    };
+
+    match head {
+        ":env" => {
+            let Some((key, value)) = rest.split_once('=') else {
+                eprintln!("Usage: :env KEY=VALUE");
+                return CommandResult::Failed;
+            };
+            let key = key.trim();
+            if key.is_empty() {
+                eprintln!("Usage: :env KEY=VALUE");
+                return CommandResult::Failed;
+            }
+            session.set_env(key, value);
+        }
+        ":unenv" => {
+            if rest.is_empty() || rest.contains(char::is_whitespace) {
+                eprintln!("Usage: :unenv KEY");
+                return CommandResult::Failed;
+            }
+            session.unset_env(rest);
+        }
+        ":args" => match split_args(rest) {
+            Ok(args) => session.set_args(args),
+            Err(e) => {
+                eprintln!("Invalid arguments: {}", e);
+                return CommandResult::Failed;
+            }
+        },
+        ":cwd" => {
+            let dir = (!rest.is_empty()).then_some(rest);
+            if let Err(e) = session.set_cwd(dir) {
+                eprintln!("rsh: {}", e);
+                return CommandResult::Failed;
+            }
+        }
+        ":stdin" => {
+            let file = (!rest.is_empty()).then_some(rest);
+            if let Err(e) = session.set_stdin(file) {
+                eprintln!("rsh: {}", e);
+                return CommandResult::Failed;
+            }
+        }
+        _ => {
+            eprintln!("rsh: internal error: handle_run_setting_command called with unknown command");
+            return CommandResult::Failed;
+        }
+    }
+    CommandResult::Handled
+}
+
+/// Handle the `:runtime` meta-command.
+///
+/// Expected syntax:
+/// `:runtime` lists the known runtimes; `:runtime <name> [flavor]` switches to one.
+pub fn handle_runtime_command(cmd: &str, session: &mut Session) -> Result<CommandResult, Box<dyn Error>> {
+    let words: Vec<&str> = cmd.split_whitespace().skip(1).collect();
+
+    if words.is_empty() {
+        let current = match session.mode() {
+            Mode::Async(runtime) => Some(runtime.name().to_string()),
+            Mode::Sync => None,
+        };
+        for name in session.runtimes().names() {
+            let marker = if current.as_deref() == Some(name) { "*" } else { " " };
+            let flavors = session.runtimes().get(name).map(|b| b.flavors()).unwrap_or_default();
+            if flavors.is_empty() {
+                println!("{} {}", marker, name);
+            } else {
+                println!("{} {} [{}]", marker, name, flavors.join("|"));
+            }
+        }
+        return Ok(CommandResult::Handled);
+    }
+    if words.len() > 2 {
+        eprintln!("Usage: :runtime [<name> [flavor]]");
+        return Ok(CommandResult::Failed);
+    }
+
+    let mut request = vec!["async"];
+    request.extend(&words);
+    switch_mode(&request, session)
+}
+
+/// Handle the `:mode` meta-command.
+///
+/// Expected syntax:
+/// `:mode` prints the current mode; `:mode sync` or `:mode async [runtime] [flavor]` switches.
+pub fn handle_mode_command(cmd: &str, session: &mut Session) -> Result<CommandResult, Box<dyn Error>> {
+    let words: Vec<&str> = cmd.split_whitespace().skip(1).collect();
+
+    if words.is_empty() {
+        println!("Mode: {}", session.mode());
+        return Ok(CommandResult::Handled);
+    }
+
+    switch_mode(&words, session)
+}
+
+fn switch_mode(words: &[&str], session: &mut Session) -> Result<CommandResult, Box<dyn Error>> {
+    let request = match ModeRequest::parse(words) {
+        Ok(request) => request,
+        Err(e) => {
+            eprintln!("Usage: :mode <sync|async [runtime] [flavor]> ({})", e);
+            return Ok(CommandResult::Failed);
+        }
+    };
+    let mode = match session.resolve_mode(&request) {
+        Ok(mode) => mode,
+        Err(e) => {
+            eprintln!("rsh: {}", e);
+            return Ok(CommandResult::Failed);
+        }
+    };
+
+    if !session.switch_mode(mode)? {
+        return Ok(CommandResult::Failed);
+    }
+    println!("Mode: {}", session.mode());
+    Ok(CommandResult::Handled)
+}
+
+/// Split a command-line string into arguments.
+///
+/// Arguments are separated by whitespace; single or double quotes group
+/// words into one argument.
+pub fn split_args(line: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_arg = false;
+    let mut quote: Option<char> = None;
+
+    for c in line.chars() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => current.push(c),
+            None if c == '"' || c == '\'' => {
+                quote = Some(c);
+                in_arg = true;
+            }
+            None if c.is_whitespace() => {
+                if in_arg {
+                    args.push(std::mem::take(&mut current));
+                    in_arg = false;
+                }
+            }
+            None => {
+                current.push(c);
+                in_arg = true;
+            }
+        }
+    }
+
+    if let Some(q) = quote {
+        return Err(format!("unterminated {} quote", q));
+    }
+    if in_arg {
+        args.push(current);
+    }
+    Ok(args)
+}
+
+/// Whether the BODY visibly needs an async context: an `.await`, an `async`
+/// block (`async { .. }`, `async move { .. }`) or an async closure
+/// (`async |x| ..`, `async move || ..`).
+///
+/// Works on tokens, so strings, chars and comments are ignored. `async fn`
+/// items compile fine in a sync body and don't count.
+pub fn body_uses_async(lines: &[String]) -> bool {
+    let tokens = tokenize(&lines.join("\n"));
+
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            Token::Punct('.') if tokens.get(i + 1) == Some(&Token::Ident("await".to_string())) => {
+                return true;
+            }
+            Token::Ident(word) if word == "async" => {
+                let mut next = tokens.get(i + 1);
+                if next == Some(&Token::Ident("move".to_string())) {
+                    next = tokens.get(i + 2);
+                }
+                if matches!(next, Some(Token::Punct('{')) | Some(Token::Punct('|'))) {
+                    return true;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    false
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Token {
+    Ident(String),
+    Punct(char),
+}
+
+/// A minimal Rust lexer: identifiers/keywords and single-character punctuation.
+/// Literals, comments and whitespace are dropped.
+fn tokenize(src: &str) -> Vec<Token> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '/' && chars.get(i + 1) == Some(&'/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && chars.get(i + 1) == Some(&'*') {
+            // Block comments nest.
+            let mut depth = 0;
+            while i < chars.len() {
+                if chars[i] == '/' && chars.get(i + 1) == Some(&'*') {
+                    depth += 1;
+                    i += 2;
+                } else if chars[i] == '*' && chars.get(i + 1) == Some(&'/') {
+                    depth -= 1;
+                    i += 2;
+                    if depth == 0 {
+                        break;
+                    }
+                } else {
+                    i += 1;
+                }
+            }
+        } else if let Some(end) = raw_string_end(&chars, i) {
+            i = end;
+        } else if c == '"' || (c == 'b' && chars.get(i + 1) == Some(&'"')) {
+            i += if c == 'b' { 2 } else { 1 };
+            while i < chars.len() && chars[i] != '"' {
+                i += if chars[i] == '\\' { 2 } else { 1 };
+            }
+            i += 1;
+        } else if c == '\'' || (c == 'b' && chars.get(i + 1) == Some(&'\'')) {
+            let start = if c == 'b' { i + 1 } else { i };
+            if chars.get(start + 1) == Some(&'\\') {
+                // Escaped char literal: skip to the closing quote.
+                i = start + 2;
+                while i < chars.len() && chars[i] != '\'' {
+                    i += 1;
+                }
+                i += 1;
+            } else if chars.get(start + 2) == Some(&'\'') {
+                i = start + 3;
+            } else {
+                // A lifetime or label; its name is lexed as an identifier next.
+                tokens.push(Token::Punct('\''));
+                i = start + 1;
+            }
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else if c.is_ascii_digit() {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+        } else {
+            tokens.push(Token::Punct(c));
+            i += 1;
+        }
+    }
+
+    tokens
+}
+
+/// If a raw (byte) string literal `r"..."`, `r#"..."#`, `br"..."` starts at `i`,
+/// return the index just past it.
+fn raw_string_end(chars: &[char], i: usize) -> Option<usize> {
+    let mut j = i;
+    if chars.get(j) == Some(&'b') {
+        j += 1;
+    }
+    if chars.get(j) != Some(&'r') {
+        return None;
+    }
+    j += 1;
+    let mut hashes = 0;
+    while chars.get(j) == Some(&'#') {
+        hashes += 1;
+        j += 1;
+    }
+    if chars.get(j) != Some(&'"') {
+        return None;
+    }
+    j += 1;
+
+    while j < chars.len() {
+        if chars[j] == '"' && chars[j + 1..].iter().take(hashes).filter(|&&c| c == '#').count() == hashes {
+            return Some(j + 1 + hashes);
+        }
+        j += 1;
+    }
+    Some(chars.len())
+}
 
 pub fn is_preamble_line(line: &str) -> bool {
     let prefixes = [
@@ -0,0 +1,117 @@
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Directory for rsh's per-project state, in the project root.
+pub const STATE_DIR: &str = ".rsh";
+
+/// Name of the binary target for session slot `slot`: `__rsh` for the first
+/// session in a project, `__rsh_1`, `__rsh_2`, … for sessions running alongside it.
+pub fn target_name(slot: usize) -> String {
+    if slot == 0 {
+        "__rsh".to_string()
+    } else {
+        format!("__rsh_{slot}")
+    }
+}
+
+/// An rsh session holding a slot in a project.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionEntry {
+    pub slot: usize,
+    pub target: String,
+    pub pid: Option<u32>,
+    /// Seconds since the Unix epoch.
+    pub started: Option<u64>,
+}
+
+/// An advisory lock on `.rsh/locks/<slot>.lock`, held for the session's lifetime.
+///
+/// Each session takes the lowest free slot, so its generated file and binary
+/// target (see [`target_name`]) are its own. The lock is released when the
+/// session is dropped or the process exits, so a crashed session's slot is
+/// simply reused.
+#[derive(Debug)]
+pub struct SessionLock {
+    slot: usize,
+    path: PathBuf,
+    _file: File,
+}
+
+impl SessionLock {
+    pub fn acquire<P: AsRef<Path>>(project_dir: P) -> io::Result<Self> {
+        let dir = locks_dir(project_dir.as_ref());
+        fs::create_dir_all(&dir)?;
+        for slot in 0.. {
+            let path = dir.join(format!("{slot}.lock"));
+            let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)?;
+            match file.try_lock() {
+                Ok(()) => {}
+                Err(TryLockError::WouldBlock) => continue,
+                Err(TryLockError::Error(e)) => return Err(e),
+            }
+
+            let started = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+            file.set_len(0)?;
+            write!(file, "pid={}\ntarget={}\nstarted={}\n", std::process::id(), target_name(slot), started)?;
+            file.flush()?;
+            return Ok(SessionLock {
+                slot,
+                path,
+                _file: file,
+            });
+        }
+        unreachable!("slots are unbounded")
+    }
+
+    pub fn slot(&self) -> usize {
+        self.slot
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Sessions currently holding a slot in the project, by slot.
+pub fn active_sessions<P: AsRef<Path>>(project_dir: P) -> Vec<SessionEntry> {
+    let Ok(entries) = fs::read_dir(locks_dir(project_dir.as_ref())) else {
+        return Vec::new();
+    };
+
+    let mut sessions: Vec<SessionEntry> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let path = entry.path();
+            let slot = path.file_stem()?.to_str()?.parse().ok()?;
+            let file = File::open(&path).ok()?;
+            // A lock we can take belongs to nobody.
+            if file.try_lock_shared().is_ok() {
+                return None;
+            }
+            Some(read_entry(slot, &path))
+        })
+        .collect();
+    sessions.sort_by_key(|s| s.slot);
+    sessions
+}
+
+fn read_entry(slot: usize, path: &Path) -> SessionEntry {
+    let text = fs::read_to_string(path).unwrap_or_default();
+    let field = |key: &str| {
+        text.lines()
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+            .map(str::to_string)
+    };
+    SessionEntry {
+        slot,
+        target: field("target").unwrap_or_else(|| target_name(slot)),
+        pid: field("pid").and_then(|p| p.parse().ok()),
+        started: field("started").and_then(|s| s.parse().ok()),
+    }
+}
+
+fn locks_dir(project_dir: &Path) -> PathBuf {
+    project_dir.join(STATE_DIR).join("locks")
+}
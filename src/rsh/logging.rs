@@ -0,0 +1,67 @@
+use std::fmt;
+
+use super::manifest::{Dependency, Manifest};
+use super::runtime::has_default_features;
+
+/// Environment variable the generated program reads its log filter from.
+pub const LOG_ENV: &str = "RUST_LOG";
+
+/// Filter used when neither `:log` nor rsh's own environment sets one.
+pub const DEFAULT_LOG_FILTER: &str = "info";
+
+/// A logging crate whose subscriber rsh installs at the start of the session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Logger {
+    TracingSubscriber,
+    EnvLogger,
+    PrettyEnvLogger,
+    SimpleLogger,
+}
+
+impl Logger {
+    /// Detection preference order: `tracing-subscriber` also forwards `log` records.
+    pub const ALL: [Logger; 4] = [Logger::TracingSubscriber, Logger::EnvLogger, Logger::PrettyEnvLogger, Logger::SimpleLogger];
+
+    pub fn package(self) -> &'static str {
+        match self {
+            Logger::TracingSubscriber => "tracing-subscriber",
+            Logger::EnvLogger => "env_logger",
+            Logger::PrettyEnvLogger => "pretty_env_logger",
+            Logger::SimpleLogger => "simple_logger",
+        }
+    }
+
+    /// Pick the first logging crate, in preference order, that the project can
+    /// initialize from its dependencies.
+    pub fn detect(manifest: &Manifest) -> Option<Logger> {
+        Logger::ALL.into_iter().find(|logger| {
+            manifest.dependency(logger.package()).is_some_and(|dep| {
+                // The `fmt` subscriber is behind a default feature.
+                *logger != Logger::TracingSubscriber || dep.has_feature("fmt") || has_default_features(dep)
+            })
+        })
+    }
+
+    /// A statement that installs the logger, filtered by `RUST_LOG`. Failures
+    /// (e.g. the session installed its own) are ignored.
+    pub fn init(self, dep: &Dependency) -> String {
+        let krate = &dep.name;
+        match self {
+            Logger::TracingSubscriber if dep.has_feature("env-filter") => format!(
+                "let _ = {krate}::fmt().with_env_filter({krate}::EnvFilter::from_default_env()).with_writer(std::io::stderr).try_init();"
+            ),
+            // Without `env-filter`, `RUST_LOG` can only be a level.
+            Logger::TracingSubscriber => format!(
+                "let _ = {krate}::fmt().with_max_level(std::env::var(\"{LOG_ENV}\").ok().and_then(|f| f.parse::<{krate}::filter::LevelFilter>().ok()).unwrap_or({krate}::filter::LevelFilter::INFO)).with_writer(std::io::stderr).try_init();"
+            ),
+            Logger::EnvLogger | Logger::PrettyEnvLogger => format!("let _ = {krate}::try_init();"),
+            Logger::SimpleLogger => format!("let _ = {krate}::SimpleLogger::new().env().init();"),
+        }
+    }
+}
+
+impl fmt::Display for Logger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.package())
+    }
+}
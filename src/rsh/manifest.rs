@@ -0,0 +1,234 @@
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use serde_json::Value as Json;
+use toml::{Table, Value};
+
+use super::cargo::{cargo_command, CargoOptions};
+
+/// A dependency of the host project that the generated `__rsh` binary can use.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dependency {
+    /// Name the crate is referred to by in Rust code (`-` replaced by `_`,
+    /// and the rename key for `package = "..."` dependencies).
+    pub name: String,
+    /// Package name as published (e.g. `async-std`).
+    pub package: String,
+    pub features: BTreeSet<String>,
+    pub default_features: bool,
+    /// Declared with `optional = true`: only available when a feature of the
+    /// host project enables it.
+    pub optional: bool,
+    /// `true` once `features` holds the resolved feature set reported by
+    /// `cargo metadata` instead of the features written in `Cargo.toml`.
+    pub resolved: bool,
+}
+
+impl Dependency {
+    pub fn has_feature(&self, feature: &str) -> bool {
+        self.features.contains(feature)
+    }
+}
+
+/// The parts of the host project's `Cargo.toml` rsh cares about.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Manifest {
+    pub package_name: Option<String>,
+    pub has_lib: bool,
+    /// `name` from the `[lib]` table, if renamed.
+    pub lib_name: Option<String>,
+    /// `edition` from `[package]`, unless inherited from the workspace.
+    pub edition: Option<String>,
+    pub dependencies: Vec<Dependency>,
+}
+
+impl Manifest {
+    /// Load and parse `Cargo.toml` at `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        let mut manifest = Manifest::parse(&text)?;
+        if let Some(dir) = path.parent() {
+            manifest.has_lib = manifest.has_lib || dir.join("src").join("lib.rs").exists();
+        }
+        Ok(manifest)
+    }
+
+    /// Parse manifest text. Only `[dependencies]` and
+    /// `[target.'...'.dependencies]` are considered; dev- and build-dependencies
+    /// are not available to a binary target.
+    pub fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        let table: Table = text.parse()?;
+
+        let package_name = table
+            .get("package")
+            .and_then(|p| p.get("name"))
+            .and_then(Value::as_str)
+            .map(str::to_string);
+        let edition = table
+            .get("package")
+            .and_then(|p| p.get("edition"))
+            .and_then(Value::as_str)
+            .map(str::to_string);
+        let has_lib = table.contains_key("lib");
+        let lib_name = table
+            .get("lib")
+            .and_then(|l| l.get("name"))
+            .and_then(Value::as_str)
+            .map(str::to_string);
+
+        let mut dependencies = Vec::new();
+        if let Some(deps) = table.get("dependencies").and_then(Value::as_table) {
+            collect_dependencies(deps, &mut dependencies);
+        }
+        if let Some(targets) = table.get("target").and_then(Value::as_table) {
+            for target in targets.values() {
+                if let Some(deps) = target.get("dependencies").and_then(Value::as_table) {
+                    collect_dependencies(deps, &mut dependencies);
+                }
+            }
+        }
+
+        Ok(Manifest {
+            package_name,
+            has_lib,
+            lib_name,
+            edition,
+            dependencies,
+        })
+    }
+
+    /// Name of the project's library crate in Rust code, if it has one.
+    pub fn library_crate(&self) -> Option<String> {
+        if !self.has_lib {
+            return None;
+        }
+        self.lib_name
+            .clone()
+            .or_else(|| self.package_name.as_ref().map(|name| name.replace('-', "_")))
+    }
+
+    /// Find a dependency by its package name. Optional dependencies are only
+    /// found once [`Manifest::resolve_features`] has seen them enabled.
+    pub fn dependency(&self, package: &str) -> Option<&Dependency> {
+        self.dependencies
+            .iter()
+            .find(|d| d.package == package && (!d.optional || d.resolved))
+    }
+
+    /// Replace the declared features with the features cargo actually resolved
+    /// (defaults, workspace inheritance and feature unification included).
+    ///
+    /// Optional dependencies that are not enabled are dropped. If `cargo metadata`
+    /// is unavailable (e.g. offline without a lock file), the manifest is left as parsed.
+    pub fn resolve_features<P: AsRef<Path>>(&mut self, dir: P, options: &CargoOptions) {
+        let Some(metadata) = cargo_metadata(dir.as_ref(), options) else {
+            return;
+        };
+        let Some(resolved) = resolved_dependencies(&metadata, &dir.as_ref().join("Cargo.toml")) else {
+            return;
+        };
+
+        self.dependencies.retain(|d| resolved.iter().any(|(name, _)| *name == d.name));
+        for dep in &mut self.dependencies {
+            if let Some((_, features)) = resolved.iter().find(|(name, _)| *name == dep.name) {
+                dep.features = features.clone();
+                dep.resolved = true;
+            }
+        }
+    }
+}
+
+fn collect_dependencies(deps: &Table, out: &mut Vec<Dependency>) {
+    for (key, value) in deps {
+        let name = key.replace('-', "_");
+        if out.iter().any(|d| d.name == name) {
+            continue;
+        }
+
+        let mut dep = Dependency {
+            name,
+            package: key.clone(),
+            features: BTreeSet::new(),
+            default_features: true,
+            optional: false,
+            resolved: false,
+        };
+
+        if let Some(table) = value.as_table() {
+            if let Some(package) = table.get("package").and_then(Value::as_str) {
+                dep.package = package.to_string();
+            }
+            if let Some(features) = table.get("features").and_then(Value::as_array) {
+                dep.features = features
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(str::to_string)
+                    .collect();
+            }
+            let default_features = table
+                .get("default-features")
+                .or_else(|| table.get("default_features"))
+                .and_then(Value::as_bool);
+            if let Some(enabled) = default_features {
+                dep.default_features = enabled;
+            }
+            dep.optional = table.get("optional").and_then(Value::as_bool).unwrap_or(false);
+        }
+
+        out.push(dep);
+    }
+}
+
+fn cargo_metadata(dir: &Path, options: &CargoOptions) -> Option<Json> {
+    let output = cargo_command("metadata", dir, options)
+        .arg("--format-version")
+        .arg("1")
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    serde_json::from_slice(&output.stdout).ok()
+}
+
+/// Extract `(extern crate name, enabled features)` for every normal dependency
+/// of the package at `manifest_path` from `cargo metadata` output.
+fn resolved_dependencies(metadata: &Json, manifest_path: &Path) -> Option<Vec<(String, BTreeSet<String>)>> {
+    let manifest_path = manifest_path.canonicalize().ok()?;
+    let root_id = metadata["packages"]
+        .as_array()?
+        .iter()
+        .find(|p| {
+            p["manifest_path"]
+                .as_str()
+                .and_then(|m| Path::new(m).canonicalize().ok())
+                .is_some_and(|m| m == manifest_path)
+        })?["id"]
+        .as_str()?;
+
+    let nodes = metadata["resolve"]["nodes"].as_array()?;
+    let node = |id: &str| nodes.iter().find(|n| n["id"].as_str() == Some(id));
+    let root = node(root_id)?;
+
+    let mut resolved = Vec::new();
+    for dep in root["deps"].as_array()? {
+        let is_normal = dep["dep_kinds"]
+            .as_array()
+            .is_some_and(|kinds| kinds.iter().any(|k| k["kind"].is_null()));
+        if !is_normal {
+            continue;
+        }
+        let (Some(name), Some(pkg)) = (dep["name"].as_str(), dep["pkg"].as_str()) else {
+            continue;
+        };
+        let features = node(pkg)
+            .and_then(|n| n["features"].as_array())
+            .map(|f| f.iter().filter_map(Json::as_str).map(str::to_string).collect())
+            .unwrap_or_default();
+        resolved.push((name.to_string(), features));
+    }
+    Some(resolved)
+}
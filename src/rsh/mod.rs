@@ -1,7 +1,36 @@
 pub mod session;
 pub mod input;
 pub mod utils;
+pub mod manifest;
+pub mod runtime;
+pub mod config;
+pub mod cli;
+pub mod cache;
+pub mod split;
+pub mod progress;
+pub mod stats;
+pub mod cargo;
+pub mod project;
+pub mod lock;
+pub mod store;
+pub mod batch;
+pub mod template;
+pub mod logging;
 
-pub use session::{Session, Segment, Mode, AsyncRuntime};
-pub use input::{Input, read_block, handle_delete_command};
+pub use session::{Session, Segment, Mode, ModeRequest, RunOutcome, RunSettings, FailureKind, FailurePolicy, Warnings, NOISE_LINTS, SessionLint};
+pub use utils::{BuildOutput, Diagnostic, RunReport};
+pub use cache::BuildCache;
+pub use split::{Layout, SplitWorkspace};
+pub use stats::{BlockTiming, SessionStats};
+pub use cargo::{CargoOptions, ProjectFiles};
+pub use lock::{SessionEntry, SessionLock};
+pub use batch::{parse_script, run_script, ScriptItem};
+pub use store::{SavedSession, SessionStore, StoredSession};
+pub use runtime::{AsyncRuntime, RuntimeBackend, RuntimeContext, RuntimeRegistry};
+pub use config::{Config, ColorChoice, UserRuntime};
+pub use template::{LineOrigin, Template, TemplateContext};
+pub use logging::{Logger, DEFAULT_LOG_FILTER, LOG_ENV};
+pub use cli::{Options, parse_args};
+pub use manifest::{Manifest, Dependency};
+pub use input::{Input, read_block, handle_command, CommandResult, handle_delete_command, handle_on_failure_command, handle_layout_command, handle_prelude_command, handle_warnings_command, handle_log_command, handle_session_command, resolve_failed_block, handle_run_setting_command, handle_runtime_command, handle_mode_command, split_args, body_uses_async, command_continues, handle_check_command};
 
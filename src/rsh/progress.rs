@@ -0,0 +1,67 @@
+use std::io::{self, IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+/// Builds that finish sooner than this never show the indicator, so fast
+/// rebuilds don't flicker.
+const SHOW_AFTER: Duration = Duration::from_millis(500);
+
+/// A one-line build indicator on stderr, redrawn in place:
+///
+/// ```text
+/// rsh: building 12.4s, 37 crates done, last: serde_derive
+/// ```
+///
+/// Only drawn when stderr is a terminal.
+pub struct Progress {
+    started: Instant,
+    enabled: bool,
+    drawn: bool,
+    crates: usize,
+    last: Option<String>,
+}
+
+impl Progress {
+    pub fn new(started: Instant) -> Self {
+        Progress {
+            started,
+            enabled: io::stderr().is_terminal(),
+            drawn: false,
+            crates: 0,
+            last: None,
+        }
+    }
+
+    /// Record a finished crate from a `compiler-artifact` message.
+    pub fn crate_done(&mut self, name: &str) {
+        self.crates += 1;
+        self.last = Some(name.to_string());
+    }
+
+    pub fn draw(&mut self) {
+        let elapsed = self.started.elapsed();
+        if !self.enabled || elapsed < SHOW_AFTER {
+            return;
+        }
+        let mut line = format!("rsh: building {:.1}s", elapsed.as_secs_f64());
+        if self.crates > 0 {
+            line.push_str(&format!(", {} crates done", self.crates));
+        }
+        if let Some(last) = &self.last {
+            line.push_str(&format!(", last: {last}"));
+        }
+        let mut stderr = io::stderr();
+        let _ = write!(stderr, "\r\x1b[2K{line}");
+        let _ = stderr.flush();
+        self.drawn = true;
+    }
+
+    /// Erase the indicator before anything else is printed.
+    pub fn clear(&mut self) {
+        if self.drawn {
+            let mut stderr = io::stderr();
+            let _ = write!(stderr, "\r\x1b[2K");
+            let _ = stderr.flush();
+            self.drawn = false;
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// First line of every file rsh generates. A file without it was written by
+/// someone else and is never overwritten or removed.
+pub const GENERATED_MARKER: &str = "// @generated by rsh";
+
+/// The full header line written at the top of generated files.
+pub fn generated_header() -> String {
+    format!("{GENERATED_MARKER}; edits are overwritten on every block.\n")
+}
+
+/// Whether the file at `path` carries rsh's header. `false` if it can't be read.
+pub fn is_generated<P: AsRef<Path>>(path: P) -> bool {
+    fs::read_to_string(path).is_ok_and(|text| text.starts_with(GENERATED_MARKER))
+}
+
+/// Check that rsh can work in `dir`: it must be a Cargo project, and an existing
+/// `__rsh.rs` at `rsh_path` must be one rsh generated.
+pub fn check_project(dir: &Path, rsh_path: &Path) -> Result<(), String> {
+    if !dir.join("Cargo.toml").is_file() {
+        return Err(format!(
+            "no Cargo.toml in {}; run rsh in a Cargo project, or use --standalone",
+            dir.display()
+        ));
+    }
+    if rsh_path.exists() && !is_generated(rsh_path) {
+        return Err(format!(
+            "{} exists and was not generated by rsh; move it away so rsh doesn't overwrite it",
+            rsh_path.display()
+        ));
+    }
+    Ok(())
+}
+
+/// Add `path` to the `info/exclude` file of the git repository containing it, so
+/// it never shows up in `git status`. Returns whether a line was added; outside a
+/// git repository (or in a worktree, whose `.git` is a file) nothing happens.
+pub fn exclude_from_git(path: &Path) -> io::Result<bool> {
+    let Some(root) = path.ancestors().skip(1).find(|dir| dir.join(".git").is_dir()) else {
+        return Ok(false);
+    };
+    let Ok(relative) = path.strip_prefix(root) else {
+        return Ok(false);
+    };
+    let pattern = format!("/{}", relative.to_string_lossy().replace('\\', "/"));
+
+    let info = root.join(".git").join("info");
+    let exclude = info.join("exclude");
+    let existing = fs::read_to_string(&exclude).unwrap_or_default();
+    if existing.lines().any(|line| line.trim() == pattern) {
+        return Ok(false);
+    }
+
+    fs::create_dir_all(&info)?;
+    let mut text = existing;
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+    text.push_str("# added by rsh\n");
+    text.push_str(&pattern);
+    text.push('\n');
+    fs::write(&exclude, text)?;
+    Ok(true)
+}
+
+/// Create a throwaway Cargo project for `--standalone` sessions outside any project.
+pub fn create_standalone_project() -> io::Result<PathBuf> {
+    let dir = std::env::temp_dir().join(format!("rsh-standalone-{}", std::process::id()));
+    fs::create_dir_all(dir.join("src"))?;
+    fs::write(
+        dir.join("Cargo.toml"),
+        "[package]\nname = \"rsh-standalone\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\n",
+    )?;
+    fs::write(dir.join("src").join("main.rs"), "fn main() {}\n")?;
+    Ok(dir)
+}
@@ -0,0 +1,525 @@
+use std::fmt;
+
+use super::config::UserRuntime;
+use super::manifest::{Dependency, Manifest};
+
+/// The async runtime a session runs under: a backend name plus an optional flavor
+/// (e.g. tokio `current_thread`). `flavor == None` lets the backend choose from
+/// the dependency's enabled features.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsyncRuntime {
+    name: String,
+    flavor: Option<String>,
+}
+
+impl AsyncRuntime {
+    pub fn new(name: &str) -> Self {
+        AsyncRuntime {
+            name: name.to_string(),
+            flavor: None,
+        }
+    }
+
+    pub fn with_flavor(name: &str, flavor: Option<&str>) -> Self {
+        AsyncRuntime {
+            name: name.to_string(),
+            flavor: flavor.map(str::to_string),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn flavor(&self) -> Option<&str> {
+        self.flavor.as_deref()
+    }
+}
+
+impl fmt::Display for AsyncRuntime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.flavor {
+            Some(flavor) => write!(f, "{} ({})", self.name, flavor),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+/// What a backend gets to generate `main` with.
+pub struct RuntimeContext<'a> {
+    /// Path the runtime crate is referred to by in the generated code.
+    pub krate: &'a str,
+    /// The host's dependency on the runtime, if it is one.
+    pub dependency: Option<&'a Dependency>,
+    pub flavor: Option<&'a str>,
+}
+
+/// An async runtime rsh knows how to detect and drive `async fn __rsh_session` with.
+pub trait RuntimeBackend {
+    /// Name used by `:runtime` and shown in `:show`.
+    fn name(&self) -> &str;
+
+    /// Package name of the dependency that provides the runtime.
+    fn package(&self) -> &str;
+
+    /// Flavors accepted by `:runtime <name> <flavor>`.
+    fn flavors(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Whether the dependency, with its enabled features, can drive a future on this platform.
+    /// `Err` carries the reason the runtime is skipped.
+    fn supports(&self, _dep: &Dependency) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Whether the dependency, with its enabled features, offers `flavor`.
+    /// `Err` carries the reason it doesn't.
+    fn supports_flavor(&self, _dep: &Dependency, _flavor: &str) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Generate a `main` that runs `__rsh_session` to completion.
+    fn main(&self, ctx: &RuntimeContext) -> String;
+}
+
+/// Exit code of the generated program when `__rsh_session` returns `Err`.
+/// Not 1 (what `fn main() -> Result<..>` uses in std), so that a block calling
+/// `std::process::exit(1)` isn't mistaken for one that returned `Err`.
+pub const SESSION_ERR_EXIT_CODE: i32 = 213;
+
+/// Exit code of a Rust program that panicked on the main thread.
+pub const PANIC_EXIT_CODE: i32 = 101;
+
+/// Code that evaluates `call` (the session's result) and, on `Err`, prints the
+/// error with `{:?}` (which includes anyhow/eyre context chains and backtraces)
+/// and exits with [`SESSION_ERR_EXIT_CODE`]. Indented by `indent`.
+pub fn report_session(call: &str, indent: &str) -> String {
+    format!(
+        "{i}if let std::result::Result::Err(e) = {call} {{\n{i}    eprintln!(\"Error: {{:?}}\", e);\n{i}    std::process::exit({code});\n{i}}}\n",
+        i = indent,
+        call = call,
+        code = SESSION_ERR_EXIT_CODE
+    )
+}
+
+/// Code that awaits `__rsh_session` and reports its error, indented by `indent`.
+pub fn await_session(indent: &str) -> String {
+    report_session("__rsh_session().await", indent)
+}
+
+/// `fn main` that hands the session future to `call(` ... `)`, e.g. `pollster::block_on`.
+fn block_on_main(call: &str) -> String {
+    let mut code = String::new();
+    code.push_str("fn main() {\n");
+    code.push_str(&format!("    {}(async {{\n", call));
+    code.push_str(&await_session("        "));
+    code.push_str("    });\n");
+    code.push_str("}\n");
+    code
+}
+
+/// `async fn main` under a runtime's attribute macro.
+fn attribute_main(attribute: &str) -> String {
+    let mut code = String::new();
+    code.push_str(&format!("#[{}]\n", attribute));
+    code.push_str("async fn main() {\n");
+    code.push_str(&await_session("    "));
+    code.push_str("}\n");
+    code
+}
+
+/// Whether a feature is enabled, treating tokio's `full` as enabling everything
+/// when only the declared (unresolved) features are known.
+pub fn tokio_has_feature(dep: &Dependency, feature: &str) -> bool {
+    dep.has_feature(feature) || (!dep.resolved && dep.has_feature("full"))
+}
+
+/// Whether the dependency's default features are on.
+pub fn has_default_features(dep: &Dependency) -> bool {
+    dep.has_feature("default") || (!dep.resolved && dep.default_features)
+}
+
+struct Tokio;
+
+impl RuntimeBackend for Tokio {
+    fn name(&self) -> &str {
+        "tokio"
+    }
+
+    fn package(&self) -> &str {
+        "tokio"
+    }
+
+    fn flavors(&self) -> Vec<String> {
+        vec!["multi_thread".to_string(), "current_thread".to_string()]
+    }
+
+    fn supports(&self, dep: &Dependency) -> Result<(), String> {
+        if tokio_has_feature(dep, "rt") || tokio_has_feature(dep, "rt-multi-thread") {
+            Ok(())
+        } else {
+            Err("its `rt` feature is not enabled".to_string())
+        }
+    }
+
+    fn supports_flavor(&self, dep: &Dependency, flavor: &str) -> Result<(), String> {
+        if flavor == "multi_thread" && !tokio_has_feature(dep, "rt-multi-thread") {
+            Err("its `rt-multi-thread` feature is not enabled; use current_thread or enable it".to_string())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn main(&self, ctx: &RuntimeContext) -> String {
+        let krate = ctx.krate;
+        let (macros, has_multi_thread) = match ctx.dependency {
+            Some(d) => (tokio_has_feature(d, "macros"), tokio_has_feature(d, "rt-multi-thread")),
+            None => (true, true),
+        };
+        let multi_thread = match ctx.flavor {
+            Some(flavor) => flavor == "multi_thread",
+            None => has_multi_thread,
+        };
+
+        if macros {
+            let mut args = Vec::new();
+            if !multi_thread {
+                args.push("flavor = \"current_thread\"".to_string());
+            }
+            if krate != "tokio" {
+                args.push(format!("crate = \"{}\"", krate));
+            }
+            if args.is_empty() {
+                attribute_main(&format!("{}::main", krate))
+            } else {
+                attribute_main(&format!("{}::main({})", krate, args.join(", ")))
+            }
+        } else {
+            let builder = if multi_thread { "new_multi_thread" } else { "new_current_thread" };
+            let mut code = String::new();
+            code.push_str("fn main() {\n");
+            code.push_str(&format!("    let runtime = {}::runtime::Builder::{}()\n", krate, builder));
+            code.push_str("        .enable_all()\n");
+            code.push_str("        .build()\n");
+            code.push_str("        .expect(\"rsh: failed to build tokio runtime\");\n");
+            code.push_str("    runtime.block_on(async {\n");
+            code.push_str(&await_session("        "));
+            code.push_str("    });\n");
+            code.push_str("}\n");
+            code
+        }
+    }
+}
+
+struct AsyncStd;
+
+impl RuntimeBackend for AsyncStd {
+    fn name(&self) -> &str {
+        "async-std"
+    }
+
+    fn package(&self) -> &str {
+        "async-std"
+    }
+
+    fn supports(&self, dep: &Dependency) -> Result<(), String> {
+        if has_default_features(dep) {
+            Ok(())
+        } else {
+            Err("its default features are disabled".to_string())
+        }
+    }
+
+    fn main(&self, ctx: &RuntimeContext) -> String {
+        // The attribute macro expands to `async_std::...` paths, so it only works unrenamed.
+        let attributes = ctx.dependency.is_none_or(|d| d.has_feature("attributes"));
+        if attributes && ctx.krate == "async_std" {
+            attribute_main("async_std::main")
+        } else {
+            block_on_main(&format!("{}::task::block_on", ctx.krate))
+        }
+    }
+}
+
+struct Smol;
+
+impl RuntimeBackend for Smol {
+    fn name(&self) -> &str {
+        "smol"
+    }
+
+    fn package(&self) -> &str {
+        "smol"
+    }
+
+    fn main(&self, ctx: &RuntimeContext) -> String {
+        // smol does not provide a proc-macro main by default; use a manual executor.
+        block_on_main(&format!("{}::block_on", ctx.krate))
+    }
+}
+
+struct ActixRt;
+
+impl RuntimeBackend for ActixRt {
+    fn name(&self) -> &str {
+        "actix-rt"
+    }
+
+    fn package(&self) -> &str {
+        "actix-rt"
+    }
+
+    fn main(&self, ctx: &RuntimeContext) -> String {
+        block_on_main(&format!("{}::System::new().block_on", ctx.krate))
+    }
+}
+
+struct Monoio;
+
+impl RuntimeBackend for Monoio {
+    fn name(&self) -> &str {
+        "monoio"
+    }
+
+    fn package(&self) -> &str {
+        "monoio"
+    }
+
+    fn supports(&self, _dep: &Dependency) -> Result<(), String> {
+        if cfg!(target_os = "linux") {
+            Ok(())
+        } else {
+            Err("it is only supported on Linux by rsh".to_string())
+        }
+    }
+
+    fn main(&self, ctx: &RuntimeContext) -> String {
+        let mut code = String::new();
+        code.push_str("fn main() {\n");
+        code.push_str(&format!(
+            "    let mut runtime = {k}::RuntimeBuilder::<{k}::FusionDriver>::new()\n",
+            k = ctx.krate
+        ));
+        code.push_str("        .enable_all()\n");
+        code.push_str("        .build()\n");
+        code.push_str("        .expect(\"rsh: failed to build monoio runtime\");\n");
+        code.push_str("    runtime.block_on(async {\n");
+        code.push_str(&await_session("        "));
+        code.push_str("    });\n");
+        code.push_str("}\n");
+        code
+    }
+}
+
+struct Glommio;
+
+impl RuntimeBackend for Glommio {
+    fn name(&self) -> &str {
+        "glommio"
+    }
+
+    fn package(&self) -> &str {
+        "glommio"
+    }
+
+    fn supports(&self, _dep: &Dependency) -> Result<(), String> {
+        if cfg!(target_os = "linux") {
+            Ok(())
+        } else {
+            Err("glommio only runs on Linux".to_string())
+        }
+    }
+
+    fn main(&self, ctx: &RuntimeContext) -> String {
+        block_on_main(&format!("{}::LocalExecutor::default().run", ctx.krate))
+    }
+}
+
+struct Compio;
+
+impl RuntimeBackend for Compio {
+    fn name(&self) -> &str {
+        "compio"
+    }
+
+    fn package(&self) -> &str {
+        "compio"
+    }
+
+    fn main(&self, ctx: &RuntimeContext) -> String {
+        let mut code = String::new();
+        code.push_str("fn main() {\n");
+        code.push_str(&format!(
+            "    let runtime = {}::runtime::Runtime::new().expect(\"rsh: failed to build compio runtime\");\n",
+            ctx.krate
+        ));
+        code.push_str("    runtime.block_on(async {\n");
+        code.push_str(&await_session("        "));
+        code.push_str("    });\n");
+        code.push_str("}\n");
+        code
+    }
+}
+
+struct Futures;
+
+impl RuntimeBackend for Futures {
+    fn name(&self) -> &str {
+        "futures"
+    }
+
+    fn package(&self) -> &str {
+        "futures"
+    }
+
+    fn supports(&self, dep: &Dependency) -> Result<(), String> {
+        if dep.has_feature("executor") || has_default_features(dep) {
+            Ok(())
+        } else {
+            Err("its `executor` feature is not enabled".to_string())
+        }
+    }
+
+    fn main(&self, ctx: &RuntimeContext) -> String {
+        block_on_main(&format!("{}::executor::block_on", ctx.krate))
+    }
+}
+
+struct Pollster;
+
+impl RuntimeBackend for Pollster {
+    fn name(&self) -> &str {
+        "pollster"
+    }
+
+    fn package(&self) -> &str {
+        "pollster"
+    }
+
+    fn main(&self, ctx: &RuntimeContext) -> String {
+        block_on_main(&format!("{}::block_on", ctx.krate))
+    }
+}
+
+/// A runtime declared by the user in `.rsh.toml`.
+///
+/// `main` is a template: `{crate}` and `{flavor}` are substituted, and a line
+/// containing only `{session}` becomes the code that awaits the session and
+/// reports its error, at that line's indentation.
+impl RuntimeBackend for UserRuntime {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn package(&self) -> &str {
+        &self.package
+    }
+
+    fn flavors(&self) -> Vec<String> {
+        self.flavors.clone()
+    }
+
+    fn main(&self, ctx: &RuntimeContext) -> String {
+        let flavor = ctx.flavor.or(self.flavors.first().map(String::as_str)).unwrap_or("");
+        let mut code = String::new();
+        for line in self.main.lines() {
+            if line.trim() == "{session}" {
+                let indent = &line[..line.len() - line.trim_start().len()];
+                code.push_str(&await_session(indent));
+            } else {
+                code.push_str(&line.replace("{crate}", ctx.krate).replace("{flavor}", flavor));
+                code.push('\n');
+            }
+        }
+        code
+    }
+}
+
+/// The runtimes a session can switch to, in detection preference order.
+pub struct RuntimeRegistry {
+    backends: Vec<Box<dyn RuntimeBackend>>,
+}
+
+impl RuntimeRegistry {
+    /// The built-in backends: tokio, async-std and smol first (in that order),
+    /// then actix-rt, monoio, glommio, compio, and finally the plain executors
+    /// `futures` and `pollster`, which are often present without being "the" runtime.
+    pub fn builtin() -> Self {
+        RuntimeRegistry {
+            backends: vec![
+                Box::new(Tokio),
+                Box::new(AsyncStd),
+                Box::new(Smol),
+                Box::new(ActixRt),
+                Box::new(Monoio),
+                Box::new(Glommio),
+                Box::new(Compio),
+                Box::new(Futures),
+                Box::new(Pollster),
+            ],
+        }
+    }
+
+    /// Built-ins plus user-declared runtimes. User runtimes are preferred during
+    /// detection and replace a built-in of the same name.
+    pub fn with_user_runtimes(runtimes: &[UserRuntime]) -> Self {
+        let mut registry = RuntimeRegistry::builtin();
+        registry
+            .backends
+            .retain(|b| !runtimes.iter().any(|r| r.name == b.name()));
+        let mut backends: Vec<Box<dyn RuntimeBackend>> = runtimes
+            .iter()
+            .map(|r| Box::new(r.clone()) as Box<dyn RuntimeBackend>)
+            .collect();
+        backends.append(&mut registry.backends);
+        RuntimeRegistry { backends }
+    }
+
+    /// Move the named runtimes to the front of the detection order, in the order
+    /// given; the rest keep their order. Returns the names no backend has.
+    pub fn prefer(&mut self, order: &[String]) -> Vec<String> {
+        let unknown = order.iter().filter(|name| self.get(name).is_none()).cloned().collect();
+        self.backends
+            .sort_by_key(|b| order.iter().position(|name| name == b.name()).unwrap_or(order.len()));
+        unknown
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn RuntimeBackend> {
+        self.backends.iter().find(|b| b.name() == name).map(|b| b.as_ref())
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.backends.iter().map(|b| b.name()).collect()
+    }
+
+    /// Pick the first runtime, in preference order, that the project depends on
+    /// with features able to drive a future. Skipped runtimes are noted on stderr.
+    pub fn detect(&self, manifest: &Manifest) -> Option<AsyncRuntime> {
+        for backend in &self.backends {
+            let Some(dep) = manifest.dependency(backend.package()) else {
+                continue;
+            };
+            match backend.supports(dep) {
+                Ok(()) => return Some(AsyncRuntime::new(backend.name())),
+                Err(reason) => {
+                    eprintln!("rsh: {} is a dependency but {}; skipping it.", backend.package(), reason);
+                }
+            }
+        }
+        None
+    }
+
+    /// Generate `main` for `runtime`, or `None` if no backend has that name.
+    pub fn main(&self, runtime: &AsyncRuntime, manifest: Option<&Manifest>) -> Option<String> {
+        let backend = self.get(runtime.name())?;
+        let dependency = manifest.and_then(|m| m.dependency(backend.package()));
+        let default_krate = backend.package().replace('-', "_");
+        let krate = dependency.map_or(default_krate.as_str(), |d| d.name.as_str());
+        Some(backend.main(&RuntimeContext {
+            krate,
+            dependency,
+            flavor: runtime.flavor(),
+        }))
+    }
+}
@@ -1,22 +1,226 @@
+use std::collections::BTreeMap;
 use std::error::Error;
+use std::fmt;
 use std::fs;
 use std::io::{self, Write};
 use std::path::{Path, PathBuf};
+use std::process::{Child, Stdio};
+use std::time::Duration;
 
-use super::input::{is_preamble_line, count_opening_braces, count_closing_braces};
-use super::utils::{run_cargo_rsh, looks_like_async_error, detect_async_runtime};
+use super::input::{is_preamble_line, count_opening_braces, count_closing_braces, body_uses_async};
+use super::cargo::{cargo_command, CargoOptions, ProjectFiles};
+use super::config::{Config, DEFAULT_PROMPT};
+use super::lock::{active_sessions, target_name, SessionEntry, SessionLock, STATE_DIR};
+use super::project::{check_project, create_standalone_project, exclude_from_git, generated_header, is_generated};
+use super::manifest::Manifest;
+use super::runtime::{report_session, AsyncRuntime, RuntimeRegistry, PANIC_EXIT_CODE, SESSION_ERR_EXIT_CODE};
+use super::cache::BuildCache;
+use super::stats::{BlockTiming, SessionStats};
+use super::store::{self, mode_request, SavedSession, SessionStore, StoredSession};
+use super::split::{publicize, Layout, SplitWorkspace, PREAMBLE_CRATE};
+use super::template::{LineOrigin, Template, TemplateContext};
+use super::logging::{Logger, DEFAULT_LOG_FILTER, LOG_ENV};
+use super::utils::{build_cargo_rsh, cargo_build, lines_in_string_literals, rustfmt, Diagnostic, cargo_rsh_command, write_if_changed, format_run_summary, looks_like_async_error, run_rsh_binary, BuildOutput, RunReport};
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mode {
+    Sync,
+    Async(AsyncRuntime),
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mode::Sync => write!(f, "sync"),
+            Mode::Async(runtime) => write!(f, "async ({})", runtime),
+        }
+    }
+}
+
+/// A mode asked for by `:mode`, `--mode` or the project config, before its
+/// runtime is resolved against the project's dependencies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModeRequest {
+    Sync,
+    /// `runtime == None` picks a runtime the same way the auto-switch does.
+    Async {
+        runtime: Option<String>,
+        flavor: Option<String>,
+    },
+}
+
+impl ModeRequest {
+    /// Parse `sync`, `async` or `async <runtime> [flavor]`.
+    pub fn parse(words: &[&str]) -> Result<Self, String> {
+        match words {
+            ["sync"] => Ok(ModeRequest::Sync),
+            ["async", rest @ ..] if rest.len() <= 2 => Ok(ModeRequest::Async {
+                runtime: rest.first().map(|s| s.to_string()),
+                flavor: rest.get(1).map(|s| s.to_string()),
+            }),
+            _ => Err("expected 'sync' or 'async [runtime] [flavor]'".to_string()),
+        }
+    }
+}
+
+/// Written like the `:mode` arguments, so it parses back.
+impl fmt::Display for ModeRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModeRequest::Sync => write!(f, "sync"),
+            ModeRequest::Async { runtime, flavor } => {
+                write!(f, "async")?;
+                for word in [runtime, flavor].into_iter().flatten() {
+                    write!(f, " {word}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// How a submitted block fared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunOutcome {
+    Success,
+    /// The generated code did not compile.
+    CompileError,
+    /// `__rsh_session` returned `Err`.
+    ReturnedErr,
+    Panicked,
+    /// Exited with another non-zero code, or was killed by a signal (`None`).
+    Failed(Option<i32>),
+    /// rsh did not build or run anything (e.g. no async runtime available).
+    NotRun,
+}
+
+impl RunOutcome {
+    fn from_status(status: std::process::ExitStatus) -> Self {
+        match status.code() {
+            Some(0) => RunOutcome::Success,
+            Some(SESSION_ERR_EXIT_CODE) => RunOutcome::ReturnedErr,
+            Some(PANIC_EXIT_CODE) => RunOutcome::Panicked,
+            code => RunOutcome::Failed(code),
+        }
+    }
+}
+
+impl fmt::Display for RunOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunOutcome::Success => write!(f, "ok"),
+            RunOutcome::CompileError => write!(f, "compile error"),
+            RunOutcome::ReturnedErr => write!(f, "returned Err"),
+            RunOutcome::Panicked => write!(f, "panicked"),
+            RunOutcome::Failed(Some(code)) => write!(f, "exit {code}"),
+            RunOutcome::Failed(None) => write!(f, "signal"),
+            RunOutcome::NotRun => write!(f, "not run"),
+        }
+    }
+}
+
+/// Which phase a failed block failed in.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum AsyncRuntime {
-    Tokio,
-    AsyncStd,
-    Smol,
+pub enum FailureKind {
+    /// The session no longer compiles.
+    Compile,
+    /// It compiled, but returned `Err`, panicked or exited non-zero.
+    Runtime,
 }
 
+impl FailureKind {
+    fn of(outcome: RunOutcome) -> Option<Self> {
+        match outcome {
+            RunOutcome::CompileError => Some(FailureKind::Compile),
+            RunOutcome::ReturnedErr | RunOutcome::Panicked | RunOutcome::Failed(_) => Some(FailureKind::Runtime),
+            RunOutcome::Success | RunOutcome::NotRun => None,
+        }
+    }
+}
+
+/// What happens to a block that failed.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum Mode {
-    Sync,
-    Async(AsyncRuntime),
+pub enum FailurePolicy {
+    /// Drop the block from the session.
+    Rollback,
+    /// Keep the block in the session anyway.
+    Keep,
+    /// Leave the decision to the user (see [`Session::pending_failure`]).
+    Ask,
+}
+
+impl FailurePolicy {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "rollback" => Some(FailurePolicy::Rollback),
+            "keep" => Some(FailurePolicy::Keep),
+            "ask" => Some(FailurePolicy::Ask),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for FailurePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FailurePolicy::Rollback => write!(f, "rollback"),
+            FailurePolicy::Keep => write!(f, "keep"),
+            FailurePolicy::Ask => write!(f, "ask"),
+        }
+    }
+}
+
+/// Lints that replaying blocks sets off by itself: a block's variables, imports
+/// and items look unused until a later block uses them.
+pub const NOISE_LINTS: [&str; 5] = ["unused_variables", "unused_imports", "unused_mut", "unused_assignments", "dead_code"];
+
+/// Lint attributes put at the top of the generated program, set with `:warnings`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Warnings {
+    /// Allow [`NOISE_LINTS`]; other warnings are shown.
+    #[default]
+    Default,
+    /// Show every warning.
+    On,
+    /// Allow all warnings.
+    Off,
+    /// Turn warnings into errors, except [`NOISE_LINTS`].
+    Deny,
+}
+
+impl Warnings {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "default" => Some(Warnings::Default),
+            "on" => Some(Warnings::On),
+            "off" => Some(Warnings::Off),
+            "deny" => Some(Warnings::Deny),
+            _ => None,
+        }
+    }
+
+    /// Crate-level attributes for this setting; a later attribute overrides an
+    /// earlier one for the lints they share.
+    pub fn attributes(self) -> Vec<String> {
+        let allow_noise = format!("#![allow({})]", NOISE_LINTS.join(", "));
+        match self {
+            Warnings::Default => vec![allow_noise],
+            Warnings::On => Vec::new(),
+            Warnings::Off => vec!["#![allow(warnings)]".to_string()],
+            Warnings::Deny => vec!["#![deny(warnings)]".to_string(), allow_noise],
+        }
+    }
+}
+
+impl fmt::Display for Warnings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warnings::Default => write!(f, "default"),
+            Warnings::On => write!(f, "on"),
+            Warnings::Off => write!(f, "off"),
+            Warnings::Deny => write!(f, "deny"),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -25,16 +229,74 @@ pub enum Segment {
     Body,
 }
 
+/// A `:clippy` lint on a PREAMBLE or BODY line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionLint {
+    pub segment: Segment,
+    /// Index of the line in its buffer, as listed by `:show`.
+    pub index: usize,
+    pub diagnostic: Diagnostic,
+}
+
+/// How the generated program is launched: environment overrides,
+/// command-line arguments, working directory and stdin source.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunSettings {
+    /// `Some(value)` sets the variable, `None` removes it from the inherited environment.
+    pub env: BTreeMap<String, Option<String>>,
+    pub args: Vec<String>,
+    pub cwd: Option<PathBuf>,
+    pub stdin: Option<PathBuf>,
+}
+
 pub struct Session {
+    /// Module-scope lines ahead of the PREAMBLE, from the config's `prelude` or
+    /// [`default_prelude`]; managed with `:prelude`.
+    prelude: Vec<String>,
     preamble: Vec<String>,
     body: Vec<String>,
     mode: Mode,
+    settings: RunSettings,
     prev_preamble_len: usize,
     prev_body_len: usize,
+    last_block: String,
+    on_compile_failure: FailurePolicy,
+    on_runtime_failure: FailurePolicy,
+    /// Set when a failed block awaits a keep/drop decision under [`FailurePolicy::Ask`].
+    pending_failure: bool,
+    last_run: Option<RunReport>,
+    last_build: Option<BuildOutput>,
+    stats: SessionStats,
+    build_cache: BuildCache,
+    layout: Layout,
+    split: SplitWorkspace,
+    warnings: Warnings,
+    /// Origin of each line of the last generated `main` source, for telling the
+    /// newest block's warnings from the rest, and that source's file as
+    /// diagnostics name it.
+    source_map: Vec<LineOrigin>,
+    mapped_file: PathBuf,
+    /// Background build of the empty session, see [`Session::start_warmup`],
+    /// with the project files as they were when it started.
+    warmup: Option<(Child, ProjectFiles)>,
+    cargo: CargoOptions,
+    /// The project is a throwaway one created by [`Session::standalone`].
+    standalone: bool,
+    /// Remove generated files on exit, see [`Session::set_cleanup`].
+    cleanup: bool,
+    /// Binary target name, `__rsh` unless another session already uses it.
+    target: String,
+    lock: Option<SessionLock>,
+    store: SessionStore,
+    /// Where this session autosaves after every successful run.
+    autosave_path: PathBuf,
     base_dir: PathBuf,
     runtime_dir: PathBuf,
     rsh_path: PathBuf,
     cargo_path: PathBuf,
+    manifest: Option<Manifest>,
+    runtimes: RuntimeRegistry,
+    config: Config,
 }
 
 impl Session {
@@ -53,27 +315,117 @@ impl Session {
         
         let cargo_path = base.join("Cargo.toml");
         let runtime_dir = base.join("src").join("bin");
-        let rsh_path = runtime_dir.join("__rsh.rs");
-        
-        Session {
+        // Sessions running side by side in one project each get their own target.
+        let lock = if cargo_path.is_file() {
+            SessionLock::acquire(&base)
+                .map_err(|e| eprintln!("rsh: could not take a session slot: {e}"))
+                .ok()
+        } else {
+            None
+        };
+        let target = target_name(lock.as_ref().map_or(0, SessionLock::slot));
+        let rsh_path = runtime_dir.join(format!("{target}.rs"));
+
+        let manifest = Manifest::load(&cargo_path).ok();
+        let config = Config::load(&base).unwrap_or_else(|e| {
+            eprintln!("rsh: ignoring invalid config: {e}");
+            Config::default()
+        });
+
+        let store = SessionStore::new(&base);
+        let autosave_path = store.autosave_path();
+
+        let mut cargo = config.cargo.clone();
+        cargo.color = config.color.enabled();
+        let mut runtimes = RuntimeRegistry::with_user_runtimes(&config.runtimes);
+        for name in runtimes.prefer(&config.runtime_order) {
+            eprintln!("rsh: ignoring unknown runtime '{name}' in runtime-order");
+        }
+
+        let mut session = Session {
+            prelude: config.prelude.clone().unwrap_or_else(|| default_prelude(manifest.as_ref())),
             preamble: Vec::new(),
             body: Vec::new(),
             mode: Mode::Sync,
+            settings: RunSettings::default(),
             prev_preamble_len: 0,
             prev_body_len: 0,
+            last_block: String::new(),
+            on_compile_failure: config.on_compile_failure.unwrap_or(FailurePolicy::Rollback),
+            on_runtime_failure: config.on_runtime_failure.unwrap_or(FailurePolicy::Rollback),
+            pending_failure: false,
+            last_run: None,
+            last_build: None,
+            stats: SessionStats::default(),
+            build_cache: BuildCache::new(&base),
+            layout: config.layout.unwrap_or(Layout::Single),
+            warnings: config.warnings,
+            source_map: Vec::new(),
+            mapped_file: PathBuf::new(),
+            split: SplitWorkspace::new(&base, &target, cargo.clone()),
+            warmup: None,
+            cargo,
+            standalone: false,
+            cleanup: true,
+            target,
+            lock,
+            store,
+            autosave_path,
             base_dir: base,
             runtime_dir,
             rsh_path,
             cargo_path,
+            manifest,
+            runtimes,
+            config,
+        };
+
+        // Whether an optional dependency (e.g. a logging crate) is enabled is
+        // only known once cargo resolved the features.
+        if session.manifest.as_ref().is_some_and(|m| m.dependencies.iter().any(|d| d.optional)) {
+            session.refresh_manifest();
+        }
+        if let Some(logger) = session.logger() {
+            let templates = [("sync", &session.config.sync_template), ("async", &session.config.async_template)];
+            for (mode, template) in templates {
+                if template.as_ref().is_some_and(|template| !template.has_init()) {
+                    eprintln!("rsh: templates.{mode} has no {{init}} line; {logger} will not be initialized.");
+                }
+            }
         }
+        session.mode = session.default_mode();
+        session
+    }
+
+    /// The mode a new or reset session starts in: the config's `mode`, or sync
+    /// if there is none or its runtime can't be used.
+    fn default_mode(&mut self) -> Mode {
+        let Some(request) = self.config.mode.clone() else {
+            return Mode::Sync;
+        };
+        self.resolve_mode(&request).unwrap_or_else(|e| {
+            eprintln!("rsh: ignoring default mode from config: {e}");
+            Mode::Sync
+        })
+    }
+
+    /// A session in a fresh throwaway project, for running rsh outside any Cargo
+    /// project (`--standalone`). The project is removed by [`Session::cleanup`].
+    pub fn standalone() -> Result<Self, Box<dyn Error>> {
+        let dir = create_standalone_project()?;
+        let mut session = Session::new(Some(&dir));
+        session.standalone = true;
+        Ok(session)
     }
 
     pub fn reset(&mut self) {
         self.preamble.clear();
         self.body.clear();
-        self.mode = Mode::Sync;
+        self.mode = self.default_mode();
         self.prev_preamble_len = 0;
         self.prev_body_len = 0;
+        self.last_block.clear();
+        self.pending_failure = false;
     }
 
 
@@ -82,6 +434,8 @@ impl Session {
         // snapshot previous successful state
         self.prev_preamble_len = self.preamble.len();
         self.prev_body_len = self.body.len();
+        self.last_block = block.to_string();
+        self.pending_failure = false;
 
         let mut in_preamble_construct = false;
         let mut brace_depth = 0;
@@ -98,7 +452,8 @@ impl Session {
             }
 
             // Check if this line starts a preamble construct
-            let starts_preamble = is_preamble_line(trimmed_start);
+            let starts_preamble = is_preamble_line(trimmed_start)
+                || self.config.preamble_prefixes.iter().any(|p| trimmed_start.starts_with(p.as_str()));
             
             // Update brace depth based on the line content
             brace_depth += count_opening_braces(trimmed_start);
@@ -122,6 +477,18 @@ impl Session {
         }
     }
 
+    /// `:show all`: the prelude, then everything [`Session::show`] prints.
+    pub fn show_all(&self) {
+        println!("--- PRELUDE ---");
+        if self.prelude.is_empty() {
+            println!("<empty>");
+        }
+        for (index, line) in self.prelude.iter().enumerate() {
+            println!("[{}] {}", index, line);
+        }
+        self.show();
+    }
+
     pub fn show(&self) {
         println!("--- PREAMBLE ---");
         if self.preamble.is_empty() {
@@ -140,7 +507,80 @@ impl Session {
             }
         }
         println!("--- MODE ---");
-        println!("{:?}", self.mode);
+        println!("{}", self.mode);
+        println!("layout: {}", self.layout);
+        println!("warnings: {}", self.warnings);
+        println!("--- RUN ---");
+        self.show_settings();
+        println!(
+            "on-failure: compile={}, runtime={}",
+            self.on_compile_failure, self.on_runtime_failure
+        );
+    }
+
+    fn show_settings(&self) {
+        let settings = &self.settings;
+        if settings.args.is_empty() {
+            println!("args: <none>");
+        } else {
+            println!("args: {:?}", settings.args);
+        }
+        match &settings.cwd {
+            Some(dir) => println!("cwd: {}", dir.display()),
+            None => println!("cwd: {} (project root)", self.base_dir.display()),
+        }
+        match &settings.stdin {
+            Some(file) => println!("stdin: {}", file.display()),
+            None => println!("stdin: <none>"),
+        }
+        if settings.env.is_empty() {
+            println!("env: <inherited>");
+        } else {
+            for (key, value) in &settings.env {
+                match value {
+                    Some(v) => println!("env: {}={}", key, v),
+                    None => println!("env: {} (unset)", key),
+                }
+            }
+        }
+    }
+
+    pub fn prelude(&self) -> &[String] {
+        &self.prelude
+    }
+
+    /// Add a line to the prelude. It must be a module-scope item, like the
+    /// lines that go to the PREAMBLE.
+    pub fn add_prelude(&mut self, line: &str) -> Result<(), String> {
+        let line = line.trim();
+        if !is_preamble_line(line) {
+            return Err(format!("'{line}' is not a module-scope item (e.g. a `use` declaration)"));
+        }
+        if self.prelude.iter().any(|existing| existing == line) {
+            return Err(format!("'{line}' is already in the prelude"));
+        }
+        self.prelude.push(line.to_string());
+        Ok(())
+    }
+
+    /// Remove the prelude line at `index`, as listed by `:prelude`.
+    pub fn remove_prelude(&mut self, index: usize) -> Result<String, String> {
+        if index >= self.prelude.len() {
+            return Err(format!("no prelude line {index} (the prelude has {})", self.prelude.len()));
+        }
+        Ok(self.prelude.remove(index))
+    }
+
+    /// The prelude as generated: imports may go unused, so they don't warn.
+    fn prelude_source(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for line in &self.prelude {
+            if line.starts_with("use ") {
+                lines.push("#[allow(unused_imports)]".to_string());
+            }
+            lines.push(line.clone());
+        }
+        lines
     }
 
     // Public getters for testing (integration tests need these)
@@ -152,11 +592,199 @@ impl Session {
         &self.body
     }
 
-    pub fn mode(&self) -> Mode {
-        self.mode
+    pub fn mode(&self) -> &Mode {
+        &self.mode
+    }
+
+    pub fn runtimes(&self) -> &RuntimeRegistry {
+        &self.runtimes
+    }
+
+    /// Switch the session to async mode under the named runtime, without running anything.
+    ///
+    /// Fails if no backend has that name, the flavor isn't one the backend offers
+    /// (or the dependency's features don't enable it), or the project doesn't
+    /// depend on the runtime's package.
+    pub fn set_runtime(&mut self, name: &str, flavor: Option<&str>) -> Result<(), String> {
+        self.mode = self.resolve_mode(&ModeRequest::Async {
+            runtime: Some(name.to_string()),
+            flavor: flavor.map(str::to_string),
+        })?;
+        Ok(())
+    }
+
+    /// Turn a requested mode into a concrete one, checking the runtime against
+    /// the project's dependencies. An async request without a runtime picks one
+    /// the same way the auto-switch does.
+    pub fn resolve_mode(&mut self, request: &ModeRequest) -> Result<Mode, String> {
+        let (name, flavor) = match request {
+            ModeRequest::Sync => return Ok(Mode::Sync),
+            ModeRequest::Async { runtime, flavor } => (runtime.as_deref(), flavor.as_deref()),
+        };
+
+        self.refresh_manifest();
+        let manifest = self.manifest.as_ref().ok_or("could not read Cargo.toml")?;
+        let Some(name) = name else {
+            return self.runtimes.detect(manifest).map(Mode::Async).ok_or_else(|| {
+                format!(
+                    "no supported async runtime found in Cargo.toml; add one of: {}",
+                    self.runtimes.names().join(", ")
+                )
+            });
+        };
+
+        let Some(backend) = self.runtimes.get(name) else {
+            return Err(format!(
+                "unknown runtime '{}'; available: {}",
+                name,
+                self.runtimes.names().join(", ")
+            ));
+        };
+        if let Some(flavor) = flavor {
+            let flavors = backend.flavors();
+            if !flavors.iter().any(|f| f == flavor) {
+                return Err(if flavors.is_empty() {
+                    format!("runtime '{}' has no flavors", name)
+                } else {
+                    format!("unknown flavor '{}' for {}; expected one of: {}", flavor, name, flavors.join(", "))
+                });
+            }
+        }
+
+        let package = backend.package();
+        let Some(dep) = manifest.dependency(package) else {
+            return Err(format!("{} is not a dependency; add it to Cargo.toml first", package));
+        };
+        backend
+            .supports(dep)
+            .map_err(|reason| format!("{} can't be used: {}", package, reason))?;
+        if let Some(flavor) = flavor {
+            backend
+                .supports_flavor(dep, flavor)
+                .map_err(|reason| format!("{} flavor of {} can't be used: {}", flavor, package, reason))?;
+        }
+
+        Ok(Mode::Async(AsyncRuntime::with_flavor(name, flavor)))
+    }
+
+    /// Switch to `mode`, regenerating and building the current session in it.
+    /// Nothing is run, so the session's side effects aren't repeated.
+    ///
+    /// If the session no longer compiles in the new mode, the previous mode is
+    /// restored and `Ok(false)` is returned. An empty session switches without
+    /// building anything.
+    pub fn switch_mode(&mut self, mode: Mode) -> Result<bool, Box<dyn Error>> {
+        let previous = std::mem::replace(&mut self.mode, mode);
+        if self.preamble.is_empty() && self.body.is_empty() {
+            return Ok(true);
+        }
+
+        if !self.runtime_dir.exists() {
+            fs::create_dir_all(&self.runtime_dir)?;
+        }
+        let build = self.build()?;
+        self.print_build_output(&build)?;
+        if build.success() {
+            return Ok(true);
+        }
+
+        eprintln!("rsh: session does not compile in {} mode; staying in {} mode.", self.mode, previous);
+        self.mode = previous;
+        if self.layout == Layout::Single {
+            self.write_rsh_bin()?;
+        }
+        Ok(false)
+    }
+
+    /// Re-read Cargo.toml, resolving the enabled features of its dependencies.
+    fn refresh_manifest(&mut self) {
+        self.manifest = match Manifest::load(&self.cargo_path) {
+            Ok(mut manifest) => {
+                let files = ProjectFiles::snapshot(&self.base_dir);
+                manifest.resolve_features(&self.base_dir, &self.cargo);
+                files.report("cargo metadata");
+                Some(manifest)
+            }
+            Err(e) => {
+                eprintln!("rsh: Error loading Cargo.toml: {e}");
+                None
+            }
+        };
+    }
+
+    pub fn settings(&self) -> &RunSettings {
+        &self.settings
+    }
+
+    /// The logging crate whose subscriber the generated program installs, if any.
+    pub fn logger(&self) -> Option<Logger> {
+        self.manifest.as_ref().and_then(Logger::detect)
+    }
+
+    /// The log filter the generated program gets in `RUST_LOG`: from `:log` (or
+    /// `:env RUST_LOG=...`), else inherited from rsh, else [`DEFAULT_LOG_FILTER`].
+    /// `None` after `:unenv RUST_LOG`.
+    pub fn log_filter(&self) -> Option<String> {
+        match self.settings.env.get(LOG_ENV) {
+            Some(filter) => filter.clone(),
+            None => Some(std::env::var(LOG_ENV).unwrap_or_else(|_| DEFAULT_LOG_FILTER.to_string())),
+        }
+    }
+
+    /// Set the log filter for the next runs; no rebuild is needed.
+    pub fn set_log_filter(&mut self, filter: &str) {
+        self.set_env(LOG_ENV, filter);
     }
 
-    pub fn delete(&mut self, segment: Segment, indices: &[usize]) {
+    /// Set an environment variable for the generated program.
+    pub fn set_env(&mut self, key: &str, value: &str) {
+        self.settings.env.insert(key.to_string(), Some(value.to_string()));
+    }
+
+    /// Remove an environment variable from the generated program's environment,
+    /// whether it was set with `:env` or inherited from rsh.
+    pub fn unset_env(&mut self, key: &str) {
+        self.settings.env.insert(key.to_string(), None);
+    }
+
+    /// Replace the arguments seen by `std::env::args()` in the generated program.
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.settings.args = args;
+    }
+
+    /// Set the working directory of the generated program.
+    /// Relative paths are resolved against the project root; `None` restores the default.
+    pub fn set_cwd<P: AsRef<Path>>(&mut self, dir: Option<P>) -> Result<(), String> {
+        let Some(dir) = dir else {
+            self.settings.cwd = None;
+            return Ok(());
+        };
+        let path = self.base_dir.join(dir.as_ref());
+        if !path.is_dir() {
+            return Err(format!("not a directory: {}", path.display()));
+        }
+        self.settings.cwd = Some(path.canonicalize().unwrap_or(path));
+        Ok(())
+    }
+
+    /// Feed a file as stdin to the generated program.
+    /// Relative paths are resolved against the project root; `None` restores an empty stdin.
+    pub fn set_stdin<P: AsRef<Path>>(&mut self, file: Option<P>) -> Result<(), String> {
+        let Some(file) = file else {
+            self.settings.stdin = None;
+            return Ok(());
+        };
+        let path = self.base_dir.join(file.as_ref());
+        if !path.is_file() {
+            return Err(format!("not a file: {}", path.display()));
+        }
+        self.settings.stdin = Some(path.canonicalize().unwrap_or(path));
+        Ok(())
+    }
+
+    /// Delete the lines at `indices` from `segment`, all or none. Returns
+    /// `false` (after printing why) if an index is out of bounds.
+    pub fn delete(&mut self, segment: Segment, indices: &[usize]) -> bool {
         let target_vec = match segment {
             Segment::Preamble => &mut self.preamble,
             Segment::Body => &mut self.body,
@@ -176,11 +804,11 @@ impl Session {
                     segment,
                     target_vec.len()
                 );
-                return;
+                return false;
             }
         } else {
             // No indices after dedup – nothing to do.
-            return;
+            return true;
         }
 
         // All indices valid; perform deletions from largest to smallest.
@@ -189,149 +817,889 @@ impl Session {
         }
 
         self.show();
+        true
     }
 
 
+    /// Return type of `__rsh_session`: the host's `anyhow` or `eyre` result type
+    /// when it depends on one of them (so `?` keeps context chains), otherwise
+    /// `Result<(), Box<dyn std::error::Error>>`. Written out in full because the
+    /// default prelude glob-imports the host library, which may export its own
+    /// `Result`.
+    fn session_result_type(&self) -> String {
+        let dep = |package: &str| self.manifest.as_ref().and_then(|m| m.dependency(package));
+        for package in ["anyhow", "eyre", "color-eyre"] {
+            if let Some(dep) = dep(package) {
+                return format!("{}::Result<()>", dep.name);
+            }
+        }
+        "std::result::Result<(), Box<dyn std::error::Error>>".to_string()
+    }
+
     pub fn write_rsh_bin(&self) -> Result<(), Box<dyn Error>> {
-        let path = &self.rsh_path;
-        let mut code = String::new();
+        let code = self.generate_rsh_bin()?;
+        self.write_generated(&code)
+    }
 
-        // Preamble at module scope.
-        for line in &self.preamble {
-            code.push_str(line);
-            code.push('\n');
+    /// Write `__rsh.rs`, unless a file there wasn't generated by rsh.
+    fn write_generated(&self, code: &str) -> Result<(), Box<dyn Error>> {
+        if self.rsh_path.exists() && !is_generated(&self.rsh_path) {
+            return Err(format!("refusing to overwrite {}: it was not generated by rsh", self.rsh_path.display()).into());
         }
-        if !self.preamble.is_empty() {
-            code.push('\n');
+        write_if_changed(&self.rsh_path, code)?;
+        Ok(())
+    }
+
+    /// Validate the project before anything is written: it must have a
+    /// `Cargo.toml`, and an existing `__rsh.rs` must carry rsh's header. Then
+    /// keep the generated file out of `git status`.
+    pub fn check_project(&self) -> Result<(), String> {
+        check_project(&self.base_dir, &self.rsh_path)?;
+        for path in [self.rsh_path.clone(), self.base_dir.join(STATE_DIR)] {
+            if let Err(e) = exclude_from_git(&path) {
+                eprintln!("rsh: could not add {} to .git/info/exclude: {e}", path.display());
+            }
         }
+        Ok(())
+    }
 
-        match self.mode {
-            Mode::Sync => {
-                code.push_str(
-                    "fn __rsh_session() -> Result<(), Box<dyn std::error::Error>> {\n",
-                );
-                for line in &self.body {
-                    code.push_str("    ");
-                    code.push_str(line);
-                    code.push('\n');
+    /// Name of this session's binary target (and of its file in `src/bin/`).
+    pub fn target_name(&self) -> &str {
+        &self.target
+    }
+
+    pub fn rsh_path(&self) -> &Path {
+        &self.rsh_path
+    }
+
+    /// The rsh sessions currently running in this project, this one included.
+    pub fn sessions(&self) -> Vec<SessionEntry> {
+        active_sessions(&self.base_dir)
+    }
+
+    /// Print the active sessions, marking this one.
+    pub fn show_sessions(&self) {
+        let own = self.lock.as_ref().map(SessionLock::slot);
+        for session in self.sessions() {
+            let pid = session.pid.map(|p| format!("pid {p}")).unwrap_or_else(|| "pid ?".to_string());
+            let marker = if Some(session.slot) == own { "  (this session)" } else { "" };
+            println!("[{}] {}  {}{}", session.slot, session.target, pid, marker);
+        }
+    }
+
+    /// The source of `__rsh.rs` for the current buffers and mode.
+    pub fn generate_rsh_bin(&self) -> Result<String, Box<dyn Error>> {
+        self.generate_main(false)
+    }
+
+    /// The source of the `__rsh` binary: the sync or async template filled with the
+    /// session. With `split`, PREAMBLE items come from the separate PREAMBLE crate
+    /// instead of being inlined at module scope.
+    fn generate_main(&self, split: bool) -> Result<String, Box<dyn Error>> {
+        Ok(self.generate_main_mapped(split)?.0)
+    }
+
+    /// [`Session::generate_main`], along with the origin of each line; PREAMBLE
+    /// origins are indices into the session's PREAMBLE.
+    fn generate_main_mapped(&self, split: bool) -> Result<(String, Vec<LineOrigin>), Box<dyn Error>> {
+        let mut preamble = Vec::new();
+        if split {
+            preamble.push("#[allow(unused_imports)]".to_string());
+            preamble.push(format!("use {}::*;", PREAMBLE_CRATE));
+            preamble.push(String::new());
+        } else {
+            // Prelude and preamble at module scope.
+            for lines in [self.prelude_source(), self.preamble.clone()] {
+                if !lines.is_empty() {
+                    preamble.extend(lines);
+                    preamble.push(String::new());
                 }
-                code.push_str("    Ok(())\n");
-                code.push_str("}\n\n");
-                code.push_str("fn main() {\n");
-                code.push_str("    if let Err(e) = __rsh_session() {\n");
-                code.push_str("        eprintln!(\"{}\", e);\n");
-                code.push_str("    }\n");
-                code.push_str("}\n");
             }
-            Mode::Async(runtime) => {
-                code.push_str(
-                    "async fn __rsh_session() -> Result<(), Box<dyn std::error::Error>> {\n",
-                );
-                for line in &self.body {
-                    code.push_str("    ");
-                    code.push_str(line);
-                    code.push('\n');
-                }
-                code.push_str("    Ok(())\n");
-                code.push_str("}\n\n");
-
-                match runtime {
-                    AsyncRuntime::Tokio => {
-                        code.push_str("#[tokio::main]\n");
-                    }
-                    AsyncRuntime::AsyncStd => {
-                        code.push_str("#[async_std::main]\n");
-                    }
-                    AsyncRuntime::Smol => {
-                        // smol does not provide a proc-macro main by default; use a manual executor.
-                        code.push_str("fn main() {\n");
-                        code.push_str("    smol::block_on(async {\n");
-                        code.push_str("        if let Err(e) = __rsh_session().await {\n");
-                        code.push_str("            eprintln!(\"{}\", e);\n");
-                        code.push_str("        }\n");
-                        code.push_str("    });\n");
-                        code.push_str("}\n");
-                        fs::write(path, code)?;
-                        return Ok(());
-                    }
-                }
+        }
+        let preamble_offset = preamble.len() - if split || self.preamble.is_empty() { 0 } else { self.preamble.len() + 1 };
 
-                code.push_str("async fn main() {\n");
-                code.push_str("    if let Err(e) = __rsh_session().await {\n");
-                code.push_str("        eprintln!(\"{}\", e);\n");
-                code.push_str("    }\n");
-                code.push_str("}\n");
+        let (template, main, runtime) = match &self.mode {
+            Mode::Sync => {
+                let main = format!("fn main() {{\n{}}}\n", report_session("__rsh_session()", "    "));
+                (self.config.sync_template.clone().unwrap_or_else(Template::sync), main, "")
             }
-        }
+            Mode::Async(runtime) => {
+                let main = self
+                    .runtimes
+                    .main(runtime, self.manifest.as_ref())
+                    .ok_or_else(|| format!("no backend for async runtime '{}'", runtime.name()))?;
+                (self.config.async_template.clone().unwrap_or_else(Template::async_), main, runtime.name())
+            }
+        };
 
-        fs::write(path, code)?;
-        Ok(())
+        let init: Vec<String> = self
+            .logger()
+            .and_then(|logger| Some(logger.init(self.manifest.as_ref()?.dependency(logger.package())?)))
+            .into_iter()
+            .collect();
+
+        let mut code = generated_header();
+        code.push_str(&self.lint_attributes());
+        let mut origins = vec![LineOrigin::Template; code.lines().count()];
+        let (rendered, rendered_origins) = template.render_mapped(&TemplateContext {
+            preamble: &preamble,
+            init: &init,
+            body: &self.body,
+            main: &main,
+            error_type: &self.session_result_type(),
+            runtime,
+        });
+        code.push_str(&rendered);
+        origins.extend(rendered_origins.into_iter().map(|origin| match origin {
+            LineOrigin::Preamble(i) if i >= preamble_offset => LineOrigin::Preamble(i - preamble_offset),
+            LineOrigin::Preamble(_) => LineOrigin::Template,
+            origin => origin,
+        }));
+        Ok((code, origins))
+    }
+
+    /// Crate-level lint attributes for `:warnings`, followed by a blank line.
+    fn lint_attributes(&self) -> String {
+        let attributes = self.warnings.attributes();
+        if attributes.is_empty() {
+            return String::new();
+        }
+        format!("{}\n\n", attributes.join("\n"))
     }
 
-    pub fn run(&mut self) -> Result<(), Box<dyn Error>> {
+    /// Build and run the session with the newest block, rolling the block back
+    /// unless it compiled and ran successfully.
+    pub fn run(&mut self) -> Result<RunOutcome, Box<dyn Error>> {
         // Ensure bin directory exists.
         if !self.runtime_dir.exists() {
             fs::create_dir_all(&self.runtime_dir)?;
         }
 
+        // A stdin file or working directory that disappeared since `:stdin` or
+        // `:cwd` is a user error, not an internal one.
+        if let Some(file) = &self.settings.stdin {
+            if !file.is_file() {
+                eprintln!("rsh: stdin file not found: {}", file.display());
+                self.rollback();
+                return Ok(RunOutcome::NotRun);
+            }
+        }
+        if let Some(dir) = &self.settings.cwd {
+            if !dir.is_dir() {
+                eprintln!("rsh: working directory not found: {}", dir.display());
+                self.rollback();
+                return Ok(RunOutcome::NotRun);
+            }
+        }
+
+        // Switch before building when the body visibly needs async, instead of
+        // paying for a failing sync build first.
+        if self.mode == Mode::Sync && self.body_looks_async() && !self.switch_to_detected_runtime() {
+            self.rollback();
+            return Ok(RunOutcome::NotRun);
+        }
+
         // First attempt in current mode.
-        self.write_rsh_bin()?;
-        let output = run_cargo_rsh(&self.base_dir)?;
+        let mut build = self.build()?;
+        let mut build_time = build.elapsed;
 
-        // io::stdout().write_all(&output.stdout)?;
-        // io::stderr().write_all(&output.stderr)?;
+        // Fall back to the compiler's verdict for async usage the token scan can't
+        // see (e.g. `.await` produced by a macro). Once async, never switch again.
+        if !build.status.success()
+            && self.mode == Mode::Sync
+            && looks_like_async_error(&build.stderr)
+        {
+            if !self.switch_to_detected_runtime() {
+                self.rollback();
+                return Ok(RunOutcome::NotRun);
+            }
+            // Regenerate in async mode and build once more.
+            build = self.build()?;
+            build_time += build.elapsed;
+        }
 
-        if output.status.success() {
-            io::stdout().write_all(&output.stdout)?;
-            io::stderr().write_all(&output.stderr)?;
-            return Ok(());
+        self.print_build_output(&build)?;
+        if !build.success() {
+            eprintln!("rsh: build {:.2}s, failed", build_time.as_secs_f64());
+            self.record_timing(build_time, &build, None, RunOutcome::CompileError);
+            self.handle_failure(RunOutcome::CompileError);
+            return Ok(RunOutcome::CompileError);
+        }
+
+        // The program couldn't be started (e.g. its stdin file went away after
+        // the check above): the block failed, not rsh.
+        let report = match self.execute(&build) {
+            Ok(report) => report,
+            Err(e) => {
+                eprintln!("rsh: could not run the program: {e}");
+                self.rollback();
+                return Ok(RunOutcome::NotRun);
+            }
+        };
+        let outcome = RunOutcome::from_status(report.status);
+        self.record_timing(build_time, &build, Some(report.elapsed), outcome);
+        if outcome == RunOutcome::Success {
+            self.autosave();
+        } else {
+            self.handle_failure(outcome);
         }
+        Ok(outcome)
+    }
+
+    /// The state written by [`Session::save_session`] and by the autosave.
+    pub fn saved(&self) -> SavedSession {
+        SavedSession {
+            preamble: self.preamble.clone(),
+            body: self.body.clone(),
+            mode: mode_request(&self.mode),
+            layout: self.layout,
+            settings: self.settings.clone(),
+            on_compile_failure: self.on_compile_failure,
+            on_runtime_failure: self.on_runtime_failure,
+        }
+    }
+
+    /// Replace the session's state with `saved`. Nothing is built or run until the
+    /// next block. A mode whose runtime is no longer usable falls back to sync, and
+    /// a working directory or stdin file that no longer exists is dropped.
+    pub fn restore(&mut self, saved: SavedSession) {
+        self.reset();
+        self.mode = self.resolve_mode(&saved.mode).unwrap_or_else(|e| {
+            eprintln!("rsh: restoring in sync mode: {e}");
+            Mode::Sync
+        });
+        self.preamble = saved.preamble;
+        self.body = saved.body;
+        self.prev_preamble_len = self.preamble.len();
+        self.prev_body_len = self.body.len();
+        self.layout = saved.layout;
+        self.on_compile_failure = saved.on_compile_failure;
+        self.on_runtime_failure = saved.on_runtime_failure;
+
+        let mut settings = saved.settings;
+        if let Some(dir) = settings.cwd.take_if(|dir| !dir.is_dir()) {
+            eprintln!("rsh: working directory {} no longer exists; using the project root.", dir.display());
+        }
+        if let Some(file) = settings.stdin.take_if(|file| !file.is_file()) {
+            eprintln!("rsh: stdin file {} no longer exists; using an empty stdin.", file.display());
+        }
+        self.settings = settings;
+    }
+
+    /// Save the session under `name` in the project's `.rsh/sessions/`.
+    pub fn save_session(&self, name: &str) -> Result<PathBuf, Box<dyn Error>> {
+        self.store.save(name, &self.saved())
+    }
+
+    /// Replace the session with the one saved under `name`.
+    pub fn open_session(&mut self, name: &str) -> Result<(), Box<dyn Error>> {
+        let saved = self.store.open(name)?;
+        self.restore(saved);
+        Ok(())
+    }
+
+    /// The project's named sessions, most recently saved first.
+    pub fn saved_sessions(&self) -> Vec<StoredSession> {
+        self.store.list()
+    }
+
+    /// Restore the most recently autosaved session of another rsh process in this
+    /// project, e.g. one that crashed or was quit.
+    pub fn resume(&mut self) -> Result<(), Box<dyn Error>> {
+        let path = self
+            .store
+            .latest_autosave(Some(&self.autosave_path))
+            .ok_or("no autosaved session to resume in this project")?;
+        let saved = store::load(&path)?;
+        self.restore(saved);
+        Ok(())
+    }
+
+    fn autosave(&self) {
+        // A standalone project is removed on exit, autosaves and all.
+        if self.standalone {
+            return;
+        }
+        if let Err(e) = self.store.autosave(&self.autosave_path, &self.saved()) {
+            eprintln!("rsh: autosave failed: {e}");
+        }
+    }
 
-        // See if error looks async-related.
-        let stderr_str = String::from_utf8_lossy(&output.stderr);
-        // not aysnc error, remove the code from session
-        if !looks_like_async_error(&stderr_str) {
-            // user code failed: roll back buffers only
-            io::stdout().write_all(&output.stdout)?;
-            io::stderr().write_all(&output.stderr)?;
-            self.preamble.truncate(self.prev_preamble_len);
-            self.body.truncate(self.prev_body_len);
+    /// Whether the BODY visibly needs an async runtime: `.await` or `async` blocks,
+    /// or one of the config's `async-patterns`.
+    fn body_looks_async(&self) -> bool {
+        body_uses_async(&self.body)
+            || self
+                .body
+                .iter()
+                .any(|line| self.config.async_patterns.iter().any(|p| line.contains(p.as_str())))
+    }
+
+    fn record_timing(&mut self, build_time: Duration, build: &BuildOutput, run: Option<Duration>, outcome: RunOutcome) {
+        self.stats.record(BlockTiming {
+            first_line: self.last_block.lines().next().unwrap_or_default().to_string(),
+            build: build_time,
+            cached: build.cached,
+            run,
+            outcome,
+        });
+    }
+
+    /// Timings of every block run in this session.
+    pub fn stats(&self) -> &SessionStats {
+        &self.stats
+    }
+
+    /// Write `__rsh.rs` and build it, reusing a cached executable when the same
+    /// source was already built with the same manifest, lock file, profile and mode.
+    fn build(&mut self) -> Result<BuildOutput, Box<dyn Error>> {
+        self.finish_warmup();
+        let files = ProjectFiles::snapshot(&self.base_dir);
+        let build = self.build_layout();
+        files.report("the build");
+        build
+    }
+
+    /// Print the compiler's diagnostics, and a hint when cargo refused to touch
+    /// the lock file or the network. Warnings in the generated program are only
+    /// shown for the newest block's lines; earlier blocks already showed theirs.
+    fn print_build_output(&self, build: &BuildOutput) -> io::Result<()> {
+        let mut stderr = io::stderr();
+        for diagnostic in self.shown_diagnostics(build) {
+            stderr.write_all(diagnostic.rendered.as_bytes())?;
+        }
+        stderr.write_all(build.cargo_stderr().as_bytes())?;
+        if build.success() {
             return Ok(());
         }
-        // yes async-liked error
-        // If already async, just keep going – do not try to switch again.
-        if matches!(self.mode, Mode::Async(_)) {
+        if build.stderr.contains("because --locked was passed") {
+            eprintln!("rsh: hint: Cargo.lock is out of date; run `cargo update` yourself, or set `locked = false` under [cargo] in .rsh.toml.");
+        } else if build.stderr.contains("--offline") {
+            eprintln!("rsh: hint: dependencies are missing offline; run `cargo fetch` yourself, or set `offline = false` under [cargo] in .rsh.toml.");
+        }
+        Ok(())
+    }
+
+    /// The diagnostics of `build` that are printed: errors, and the warnings
+    /// [`Session::shows_warning`] lets through.
+    pub fn shown_diagnostics<'a>(&self, build: &'a BuildOutput) -> Vec<&'a Diagnostic> {
+        build
+            .diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.level != "warning" || self.shows_warning(diagnostic))
+            .collect()
+    }
+
+    /// Whether a warning is worth showing: it points into the newest block, or
+    /// outside the generated program (e.g. the host's library).
+    fn shows_warning(&self, diagnostic: &Diagnostic) -> bool {
+        let Some(file) = &diagnostic.file else {
+            // Counts like `3 warnings emitted` include the hidden ones.
+            return !diagnostic.message.ends_with("emitted");
+        };
+        if !file.ends_with(&self.mapped_file) {
+            return true;
+        }
+        let origin = diagnostic.line.and_then(|line| self.source_map.get(line.checked_sub(1)?));
+        match origin {
+            Some(LineOrigin::Preamble(i)) => *i >= self.prev_preamble_len,
+            Some(LineOrigin::Body(i)) => *i >= self.prev_body_len,
+            _ => false,
+        }
+    }
+
+    fn set_source_map(&mut self, layout: Layout, source_map: Vec<LineOrigin>) {
+        self.mapped_file = self.generated_main_file(layout);
+        self.source_map = source_map;
+    }
+
+    /// Path of the generated `main` source as diagnostics name it: relative to the
+    /// package root, which may itself be inside a workspace.
+    fn generated_main_file(&self, layout: Layout) -> PathBuf {
+        match layout {
+            Layout::Single => PathBuf::from("src/bin").join(format!("{}.rs", self.target)),
+            Layout::Split => PathBuf::from("body/main.rs"),
+        }
+    }
+
+    /// Run `cargo clippy` on the session's target (in the single layout) and
+    /// return the lints on PREAMBLE and BODY lines, in the order clippy reports
+    /// them. If the session doesn't compile, its errors are printed and `Err` returned.
+    pub fn clippy(&mut self) -> Result<Vec<SessionLint>, Box<dyn Error>> {
+        self.finish_warmup();
+        if !self.runtime_dir.exists() {
+            fs::create_dir_all(&self.runtime_dir)?;
+        }
+        let (source, source_map) = self.generate_main_mapped(false)?;
+        self.write_generated(&source)?;
+
+        let mut cmd = cargo_command("clippy", &self.base_dir, &self.cargo);
+        cmd.arg("--bin").arg(&self.target);
+        let files = ProjectFiles::snapshot(&self.base_dir);
+        let output = cargo_build(cmd, &self.target);
+        files.report("cargo clippy");
+        let output = output?;
+        if !output.status.success() {
+            for diagnostic in output.diagnostics.iter().filter(|d| d.level == "error") {
+                eprint!("{}", diagnostic.rendered);
+            }
+            eprint!("{}", output.cargo_stderr());
+            return Err("the session does not compile".into());
+        }
+
+        let main = self.generated_main_file(Layout::Single);
+        let lints = output
+            .diagnostics
+            .into_iter()
+            .filter_map(|diagnostic| {
+                if !diagnostic.file.as_ref().is_some_and(|file| file.ends_with(&main)) {
+                    return None;
+                }
+                let (segment, index) = match source_map.get(diagnostic.line?.checked_sub(1)?)? {
+                    LineOrigin::Preamble(i) => (Segment::Preamble, *i),
+                    LineOrigin::Body(i) => (Segment::Body, *i),
+                    _ => return None,
+                };
+                Some(SessionLint { segment, index, diagnostic })
+            })
+            .collect();
+        Ok(lints)
+    }
+
+    /// Format the PREAMBLE and BODY with rustfmt, in the project's edition.
+    /// Lines stay in the buffer they came from; a line may be split or joined.
+    /// Returns whether anything changed. `Err` carries rustfmt's message, and
+    /// the buffers are left alone.
+    pub fn format(&mut self) -> Result<bool, String> {
+        const WRAPPER: &str = "fn __rsh_fmt() {";
+        let mut source = String::new();
+        for line in &self.preamble {
+            source.push_str(line);
+            source.push('\n');
+        }
+        source.push_str(WRAPPER);
+        source.push('\n');
+        for line in &self.body {
+            source.push_str(line);
+            source.push('\n');
+        }
+        source.push_str("}\n");
+
+        let edition = self.manifest.as_ref().and_then(|m| m.edition.as_deref()).unwrap_or("2021");
+        let formatted = rustfmt(&source, edition)?;
+        let lines: Vec<&str> = formatted.lines().collect();
+        // An empty BODY comes back as `fn __rsh_fmt() {}`.
+        let wrapper = lines
+            .iter()
+            .position(|line| line.starts_with(WRAPPER))
+            .ok_or("rustfmt output is missing the BODY")?;
+
+        let mut preamble: Vec<String> = lines[..wrapper].iter().map(|line| line.to_string()).collect();
+        while preamble.last().is_some_and(|line| line.is_empty()) {
+            preamble.pop();
+        }
+        let body_end = lines.iter().rposition(|line| *line == "}").filter(|end| *end > wrapper).unwrap_or(wrapper + 1);
+        // Take off the wrapper's indentation, except where a line continues a
+        // string literal: that text is part of the string.
+        let in_literal = lines_in_string_literals(&formatted);
+        let body: Vec<String> = ((wrapper + 1).min(body_end)..body_end)
+            .map(|i| {
+                let line = lines[i];
+                if in_literal[i] {
+                    line.to_string()
+                } else {
+                    line.strip_prefix("    ").unwrap_or(line).to_string()
+                }
+            })
+            .collect();
+
+        if preamble == self.preamble && body == self.body {
+            return Ok(false);
+        }
+        self.preamble = preamble;
+        self.body = body;
+        self.prev_preamble_len = self.preamble.len();
+        self.prev_body_len = self.body.len();
+        self.autosave();
+        Ok(true)
+    }
+
+    /// Type-check the session with `cargo check`, with `block` added as a
+    /// candidate when given, and print the diagnostics. Nothing runs, and the
+    /// session is left as it was. Returns whether it compiled.
+    pub fn check(&mut self, block: Option<&str>) -> Result<bool, Box<dyn Error>> {
+        let preamble = self.preamble.clone();
+        let body = self.body.clone();
+        let (prev_preamble_len, prev_body_len) = (self.prev_preamble_len, self.prev_body_len);
+        let last_block = self.last_block.clone();
+        let pending_failure = self.pending_failure;
+
+        if let Some(block) = block {
+            self.add_code_block(block);
+        }
+        let checked = self.check_current();
+
+        self.preamble = preamble;
+        self.body = body;
+        self.prev_preamble_len = prev_preamble_len;
+        self.prev_body_len = prev_body_len;
+        self.last_block = last_block;
+        self.pending_failure = pending_failure;
+        checked
+    }
+
+    fn check_current(&mut self) -> Result<bool, Box<dyn Error>> {
+        self.finish_warmup();
+        if !self.runtime_dir.exists() {
+            fs::create_dir_all(&self.runtime_dir)?;
+        }
+        let (source, source_map) = self.generate_main_mapped(false)?;
+        self.set_source_map(Layout::Single, source_map);
+        self.write_generated(&source)?;
+
+        let mut cmd = cargo_command("check", &self.base_dir, &self.cargo);
+        cmd.arg("--bin").arg(&self.target);
+        let files = ProjectFiles::snapshot(&self.base_dir);
+        let output = cargo_build(cmd, &self.target);
+        files.report("cargo check");
+        let output = output?;
+
+        self.print_build_output(&output)?;
+
+        let ok = output.status.success();
+        if !ok && self.mode == Mode::Sync && looks_like_async_error(&output.stderr) {
+            eprintln!("rsh: hint: this needs async mode; running it switches automatically, or use :mode async.");
+        }
+        eprintln!("rsh: check {:.2}s, {}", output.elapsed.as_secs_f64(), if ok { "ok" } else { "failed" });
+        Ok(ok)
+    }
+
+    /// The lint setting of the generated program.
+    pub fn warnings(&self) -> Warnings {
+        self.warnings
+    }
+
+    /// Change the lint attributes of the generated program, from the next build.
+    pub fn set_warnings(&mut self, warnings: Warnings) {
+        self.warnings = warnings;
+    }
+
+    fn build_layout(&mut self) -> Result<BuildOutput, Box<dyn Error>> {
+        if self.layout == Layout::Split {
+            match self.build_split() {
+                Ok(build) => return Ok(build),
+                Err(e) => {
+                    eprintln!("rsh: split layout unavailable: {e}; using the single layout.");
+                    self.layout = Layout::Single;
+                }
+            }
+        }
+
+        let (source, source_map) = self.generate_main_mapped(false)?;
+        self.set_source_map(Layout::Single, source_map);
+        self.write_generated(&source)?;
+        self.build_cached(&source, |session| build_cargo_rsh(&session.base_dir, &session.target, &session.cargo))
+    }
+
+    /// Start building the empty session in the background, so the project's
+    /// dependencies compile while the first block is being typed. The first real
+    /// build waits for it instead of racing it.
+    pub fn start_warmup(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.warmup.is_some() {
             return Ok(());
         }
+        let mut cmd = match self.layout {
+            Layout::Single => {
+                if !self.runtime_dir.exists() {
+                    fs::create_dir_all(&self.runtime_dir)?;
+                }
+                self.write_generated(&self.generate_rsh_bin()?)?;
+                cargo_rsh_command(&self.base_dir, &self.target, &self.cargo)
+            }
+            Layout::Split => {
+                self.split.write(&self.split_preamble(), &self.generate_main(true)?)?;
+                self.split.body_command()
+            }
+        };
+        // Errors surface in the first real build.
+        cmd.arg("--quiet")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        let files = ProjectFiles::snapshot(&self.base_dir);
+        self.warmup = Some((cmd.spawn()?, files));
+        Ok(())
+    }
+
+    /// Whether the warm-up build is still running.
+    pub fn warming_up(&mut self) -> bool {
+        self.warmup.as_mut().is_some_and(|(child, _)| matches!(child.try_wait(), Ok(None)))
+    }
+
+    /// Wait for the warm-up build, if one was started.
+    fn finish_warmup(&mut self) {
+        let Some((mut child, files)) = self.warmup.take() else {
+            return;
+        };
+        if matches!(child.try_wait(), Ok(None)) {
+            eprintln!("rsh: waiting for the warm-up build to finish...");
+        }
+        let _ = child.wait();
+        files.report("the warm-up build");
+    }
+
+    /// Build the split layout: the PREAMBLE crate (only when it changed) and the BODY binary.
+    fn build_split(&mut self) -> Result<BuildOutput, Box<dyn Error>> {
+        let preamble = self.split_preamble();
+        let (main, source_map) = self.generate_main_mapped(true)?;
+        self.set_source_map(Layout::Split, source_map);
+        self.split.write(&preamble, &main)?;
+
+        let source = format!("{preamble}\0{main}");
+        self.build_cached(&source, |session| session.split.build(&preamble))
+    }
+
+    /// Source of the PREAMBLE crate: prelude and PREAMBLE, made public.
+    fn split_preamble(&self) -> String {
+        let lines: Vec<String> = self.prelude_source().into_iter().chain(self.preamble.iter().cloned()).collect();
+        format!("{}{}", self.lint_attributes(), publicize(&lines))
+    }
 
-        // Try to detect a runtime from Cargo.toml.
-        let runtime = detect_async_runtime(&self.cargo_path);
+    /// Reuse the cached executable for `source`, or build with `build` and cache the result.
+    fn build_cached(
+        &mut self,
+        source: &str,
+        build: impl FnOnce(&mut Self) -> Result<BuildOutput, Box<dyn Error>>,
+    ) -> Result<BuildOutput, Box<dyn Error>> {
+        let variant = format!("{} {} [{}]", self.mode, self.layout, self.cargo.features.join(","));
+        let profile = self.cargo.profile();
+        let inputs = self.build_cache.inputs();
+        let key = self.build_cache.key(inputs, source, profile, &variant);
+        let output = match self.build_cache.get(&key) {
+            Some(executable) => BuildOutput {
+                status: Default::default(),
+                stderr: String::new(),
+                diagnostics: Vec::new(),
+                executable: Some(executable),
+                elapsed: Default::default(),
+                preamble_elapsed: None,
+                cached: true,
+            },
+            None => {
+                let mut output = build(self)?;
+                if let Some(executable) = output.executable.as_deref().filter(|_| output.status.success()) {
+                    // The build may have just created or updated Cargo.lock.
+                    let key = self.build_cache.key(inputs, source, profile, &variant);
+                    // Run the cached copy; cargo overwrites its own output on the next build.
+                    output.executable = Some(self.build_cache.insert(&key, executable)?);
+                }
+                output
+            }
+        };
+        self.last_build = Some(output.clone());
+        Ok(output)
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Choose how the next builds are laid out; takes effect with the next block.
+    pub fn set_layout(&mut self, layout: Layout) {
+        self.layout = layout;
+    }
+
+    /// The effective configuration the session started with.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// The prompt for a new block, from the config's `prompt` format.
+    pub fn prompt(&mut self) -> String {
+        let status = if self.warming_up() { " (warming up)" } else { "" };
+        let prompt = self
+            .config
+            .prompt
+            .as_deref()
+            .unwrap_or(DEFAULT_PROMPT)
+            .replace("{status}", status)
+            .replace("{mode}", &self.mode.to_string())
+            .replace("{target}", &self.target);
+        if self.cargo.color {
+            format!("\x1b[1;32m{prompt}\x1b[0m")
+        } else {
+            prompt
+        }
+    }
+
+    pub fn cargo_options(&self) -> &CargoOptions {
+        &self.cargo
+    }
+
+    /// Replace the flags of every cargo invocation, e.g. with `--features` or
+    /// `--release` from the command line. Takes effect with the next build.
+    pub fn set_cargo_options(&mut self, options: CargoOptions) {
+        self.split.set_cargo_options(options.clone());
+        self.cargo = options;
+    }
+
+    /// Whether [`Session::cleanup`] removes the generated file (and a standalone
+    /// project); off with `--no-cleanup`.
+    pub fn set_cleanup(&mut self, enabled: bool) {
+        self.cleanup = enabled;
+    }
+
+    /// The scratch workspace used by the split layout.
+    pub fn split_workspace(&self) -> &Path {
+        self.split.dir()
+    }
+
+    /// The last build, successful or not.
+    pub fn last_build(&self) -> Option<&BuildOutput> {
+        self.last_build.as_ref()
+    }
+
+    /// Whether the last build reused a cached executable instead of running cargo.
+    pub fn last_build_was_cached(&self) -> bool {
+        self.last_build.as_ref().is_some_and(|build| build.cached)
+    }
+
+    /// Run the built program and print how long building and running took.
+    fn execute(&mut self, build: &BuildOutput) -> Result<RunReport, Box<dyn Error>> {
+        let executable = build.executable.as_deref().ok_or("no executable to run")?;
+        io::stdout().flush()?;
+        let mut settings = self.settings.clone();
+        if self.logger().is_some() && !settings.env.contains_key(LOG_ENV) && std::env::var_os(LOG_ENV).is_none() {
+            settings.env.insert(LOG_ENV.to_string(), Some(DEFAULT_LOG_FILTER.to_string()));
+        }
+        let report = run_rsh_binary(executable, &self.base_dir, &settings)?;
+        eprintln!("rsh: {}", format_run_summary(build, &report));
+        self.last_run = Some(report);
+        Ok(report)
+    }
+
+    /// How the program ran the last time it was built and run.
+    pub fn last_run(&self) -> Option<&RunReport> {
+        self.last_run.as_ref()
+    }
+
+    /// Apply the failure policy for `outcome` to the newest block.
+    fn handle_failure(&mut self, outcome: RunOutcome) {
+        let Some(kind) = FailureKind::of(outcome) else {
+            return;
+        };
+        let what = match outcome {
+            RunOutcome::CompileError => "failed to compile".to_string(),
+            RunOutcome::ReturnedErr => "returned an error".to_string(),
+            RunOutcome::Panicked => "panicked".to_string(),
+            RunOutcome::Failed(Some(code)) => format!("exited with status {code}"),
+            RunOutcome::Failed(None) => match self.last_run.and_then(|r| r.signal()) {
+                Some(signal) => format!("was terminated by signal {signal}"),
+                None => "was terminated by a signal".to_string(),
+            },
+            RunOutcome::Success | RunOutcome::NotRun => return,
+        };
+
+        match self.failure_policy(kind) {
+            FailurePolicy::Rollback => {
+                // Compile errors speak for themselves.
+                if kind == FailureKind::Runtime {
+                    eprintln!("rsh: session {what}; block dropped.");
+                }
+                self.rollback();
+            }
+            FailurePolicy::Keep => eprintln!("rsh: session {what}; block kept."),
+            FailurePolicy::Ask => {
+                eprintln!("rsh: session {what}.");
+                self.pending_failure = true;
+            }
+        }
+    }
+
+    pub fn failure_policy(&self, kind: FailureKind) -> FailurePolicy {
+        match kind {
+            FailureKind::Compile => self.on_compile_failure,
+            FailureKind::Runtime => self.on_runtime_failure,
+        }
+    }
+
+    pub fn set_failure_policy(&mut self, kind: FailureKind, policy: FailurePolicy) {
+        match kind {
+            FailureKind::Compile => self.on_compile_failure = policy,
+            FailureKind::Runtime => self.on_runtime_failure = policy,
+        }
+    }
+
+    /// The failed block awaiting a keep/drop decision, if any.
+    pub fn pending_failure(&self) -> Option<&str> {
+        self.pending_failure.then_some(self.last_block.as_str())
+    }
+
+    /// Resolve a pending failure by keeping the block.
+    pub fn keep_last_block(&mut self) {
+        self.pending_failure = false;
+    }
+
+    /// Resolve a pending failure by dropping the block.
+    pub fn drop_last_block(&mut self) {
+        if self.pending_failure {
+            self.rollback();
+            self.pending_failure = false;
+        }
+    }
+
+    /// Switch to async mode with the runtime detected from Cargo.toml.
+    /// Returns `false`, after explaining why, if there is none.
+    fn switch_to_detected_runtime(&mut self) -> bool {
+        // Try to detect a runtime from Cargo.toml, using the features cargo resolved.
+        self.refresh_manifest();
+        let runtime = self.manifest.as_ref().and_then(|m| self.runtimes.detect(m));
         let Some(runtime) = runtime else {
             eprintln!("rsh: Async usage detected (`await` or async error), but no supported async runtime was found in Cargo.toml.");
-            eprintln!("rsh: Please add one of: tokio, async-std, or smol to your Cargo.toml and try again.");
-            self.preamble.truncate(self.prev_preamble_len);
-            self.body.truncate(self.prev_body_len);
-            return Ok(());
+            eprintln!(
+                "rsh: Please add one of: {} to your Cargo.toml and try again.",
+                self.runtimes.names().join(", ")
+            );
+            return false;
         };
 
+        eprintln!("rsh: Detected async usage; switching to async mode with runtime: {}.", runtime);
         self.mode = Mode::Async(runtime);
-        eprintln!("rsh: Detected async usage; switching to async mode with runtime: {:?}.", runtime);
-
-        // Regenerate in async mode and rerun once.
-        self.write_rsh_bin()?;
-        let output2 = run_cargo_rsh(&self.base_dir)?;
-        io::stdout().write_all(&output2.stdout)?;
-        io::stderr().write_all(&output2.stderr)?;
+        true
+    }
 
-        Ok(())
+    /// Drop the lines added by the last block.
+    fn rollback(&mut self) {
+        self.preamble.truncate(self.prev_preamble_len);
+        self.body.truncate(self.prev_body_len);
     }
 
-    pub fn cleanup(&self) {
-        if self.rsh_path.exists() {
+    pub fn cleanup(&mut self) {
+        if let Some((mut child, _)) = self.warmup.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        if !self.cleanup {
+            eprintln!("rsh: leaving {} in place.", if self.standalone { self.base_dir.display() } else { self.rsh_path.display() });
+            return;
+        }
+        // Never remove a file the user wrote.
+        if self.rsh_path.exists() && is_generated(&self.rsh_path) {
             if let Err(e) = fs::remove_file(&self.rsh_path) {
-                eprintln!("rsh: failed to remove generated __rsh.rs: {e}");
+                eprintln!("rsh: failed to remove generated {}: {e}", self.rsh_path.display());
             }
         }
+        if self.standalone {
+            let _ = fs::remove_dir_all(&self.base_dir);
+        }
     }
 }
 
+/// The prelude when the config doesn't set one: a glob import of the project's
+/// library, so its items are usable without typing the `use`.
+fn default_prelude(manifest: Option<&Manifest>) -> Vec<String> {
+    manifest
+        .and_then(Manifest::library_crate)
+        .map(|krate| vec![format!("use {krate}::*;")])
+        .unwrap_or_default()
+}
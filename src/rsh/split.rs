@@ -0,0 +1,410 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use toml::{Table, Value};
+
+use super::cargo::{cargo_command, CargoOptions};
+use super::input::{count_closing_braces, count_opening_braces};
+use super::utils::{cargo_build, target_dir, write_if_changed, BuildOutput};
+
+/// Crate name the BODY binary imports PREAMBLE items from in the split layout.
+pub const PREAMBLE_CRATE: &str = "rsh_preamble";
+
+/// How the generated code is laid out for building.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// PREAMBLE and BODY in one `src/bin/__rsh.rs` of the host project.
+    Single,
+    /// PREAMBLE in its own library crate that is only rebuilt when it changes, and
+    /// BODY in a binary depending on it, both in a scratch workspace.
+    Split,
+}
+
+impl Layout {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "single" => Some(Layout::Single),
+            "split" => Some(Layout::Split),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Layout::Single => write!(f, "single"),
+            Layout::Split => write!(f, "split"),
+        }
+    }
+}
+
+/// The scratch workspace of the split layout, in `<target dir>/rsh-split/<bin>/`:
+///
+/// ```text
+/// Cargo.toml          [workspace] with both members
+/// preamble/lib.rs     PREAMBLE items, made `pub`       (crate `rsh_preamble`)
+/// body/main.rs        `use rsh_preamble::*;` and BODY  (bin `__rsh`, or the session's target)
+/// ```
+///
+/// Both crates get the host's dependencies (and the host library, if any), and
+/// build into the host's target directory so compiled dependencies are shared.
+pub struct SplitWorkspace {
+    dir: PathBuf,
+    project_dir: PathBuf,
+    target_dir: PathBuf,
+    /// PREAMBLE source last compiled successfully on its own.
+    built_preamble: Option<String>,
+    /// Name of the BODY binary target, the session's target name.
+    bin: String,
+    cargo: CargoOptions,
+}
+
+impl SplitWorkspace {
+    pub fn new<P: AsRef<Path>>(project_dir: P, bin: &str, cargo: CargoOptions) -> Self {
+        let project_dir = project_dir.as_ref().to_path_buf();
+        let target_dir = target_dir(&project_dir);
+        SplitWorkspace {
+            dir: target_dir.join("rsh-split").join(bin),
+            project_dir,
+            target_dir,
+            built_preamble: None,
+            bin: bin.to_string(),
+            cargo,
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn set_cargo_options(&mut self, cargo: CargoOptions) {
+        self.cargo = cargo;
+    }
+
+    /// Write the workspace for the given crate sources. Unchanged files are left
+    /// alone so cargo only rebuilds what changed.
+    pub fn write(&self, preamble: &str, body: &str) -> Result<(), Box<dyn Error>> {
+        let [root, preamble_manifest, body_manifest] = self.manifests()?;
+        fs::create_dir_all(self.dir.join("preamble"))?;
+        fs::create_dir_all(self.dir.join("body"))?;
+        write_if_changed(self.dir.join("Cargo.toml"), &root)?;
+        write_if_changed(self.dir.join("preamble").join("Cargo.toml"), &preamble_manifest)?;
+        write_if_changed(self.dir.join("body").join("Cargo.toml"), &body_manifest)?;
+        write_if_changed(self.dir.join("preamble").join("lib.rs"), preamble)?;
+        write_if_changed(self.dir.join("body").join("main.rs"), body)?;
+
+        // Start from the host's lock file so both resolve the same versions.
+        let host_lock = self.project_dir.join("Cargo.lock");
+        let lock = self.dir.join("Cargo.lock");
+        if host_lock.exists() && !lock.exists() {
+            fs::copy(host_lock, lock)?;
+        }
+        Ok(())
+    }
+
+    /// Build the BODY binary. When `preamble` differs from the last PREAMBLE built,
+    /// the library is built first on its own, so its compile time can be reported.
+    pub fn build(&mut self, preamble: &str) -> Result<BuildOutput, Box<dyn Error>> {
+        let mut preamble_elapsed = None;
+        if self.built_preamble.as_deref() != Some(preamble) {
+            let mut cmd = self.cargo_build();
+            cmd.arg("--package").arg("rsh-preamble");
+            let build = cargo_build(cmd, &self.bin)?;
+            if !build.status.success() {
+                return Ok(build);
+            }
+            self.built_preamble = Some(preamble.to_string());
+            preamble_elapsed = Some(build.elapsed);
+        }
+
+        let mut build = cargo_build(self.body_command(), &self.bin)?;
+        if let Some(elapsed) = preamble_elapsed {
+            build.elapsed += elapsed;
+            build.preamble_elapsed = Some(elapsed);
+        }
+        Ok(build)
+    }
+
+    /// `cargo build --bin <bin>` for the BODY binary (and the PREAMBLE crate with it).
+    pub fn body_command(&self) -> Command {
+        let mut cmd = self.cargo_build();
+        cmd.arg("--bin").arg(&self.bin);
+        cmd
+    }
+
+    fn cargo_build(&self) -> Command {
+        // The scratch lock file is rsh's own and gains entries for the two crates,
+        // so only `--offline` applies here.
+        let options = CargoOptions {
+            locked: false,
+            features: Vec::new(),
+            ..self.cargo.clone()
+        };
+        let mut cmd = cargo_command("build", &self.dir, &options);
+        cmd.arg("--target-dir").arg(&self.target_dir);
+        cmd
+    }
+
+    /// Manifests for the workspace root, the PREAMBLE crate and the BODY crate.
+    fn manifests(&self) -> Result<[String; 3], Box<dyn Error>> {
+        if !self.cargo.features.is_empty() {
+            // Features can toggle optional dependencies, which the copied manifests don't model.
+            return Err("the split layout doesn't support --features".into());
+        }
+        let host: Table = fs::read_to_string(self.project_dir.join("Cargo.toml"))?.parse()?;
+        let package = host.get("package").and_then(Value::as_table);
+        let edition = package
+            .and_then(|p| p.get("edition"))
+            .and_then(Value::as_str)
+            .unwrap_or("2021");
+
+        let mut dependencies = Table::new();
+        if let Some(deps) = host.get("dependencies").and_then(Value::as_table) {
+            self.copy_dependencies(deps, &mut dependencies)?;
+        }
+        // Platform-specific dependencies keep their `[target.'cfg(..)']` section.
+        let mut targets = Table::new();
+        if let Some(host_targets) = host.get("target").and_then(Value::as_table) {
+            for (cfg, target) in host_targets {
+                if let Some(deps) = target.get("dependencies").and_then(Value::as_table) {
+                    let mut copied = Table::new();
+                    self.copy_dependencies(deps, &mut copied)?;
+                    let mut section = Table::new();
+                    section.insert("dependencies".into(), Value::Table(copied));
+                    targets.insert(cfg.clone(), Value::Table(section));
+                }
+            }
+        }
+        let has_lib = host.contains_key("lib") || self.project_dir.join("src").join("lib.rs").exists();
+        if let Some(name) = package.and_then(|p| p.get("name")).and_then(Value::as_str).filter(|_| has_lib) {
+            dependencies.insert(name.to_string(), path_dependency(&self.project_dir));
+        }
+
+        let mut root = Table::new();
+        let mut workspace = Table::new();
+        workspace.insert("members".into(), Value::Array(vec!["preamble".into(), "body".into()]));
+        workspace.insert("resolver".into(), "2".into());
+        root.insert("workspace".into(), Value::Table(workspace));
+
+        let crate_manifest = |name: &str, target_key: &str, target: Table, extra: Option<(&str, Value)>| {
+            let mut package = Table::new();
+            package.insert("name".into(), name.into());
+            package.insert("version".into(), "0.0.0".into());
+            package.insert("edition".into(), edition.into());
+            package.insert("publish".into(), false.into());
+
+            let mut deps = dependencies.clone();
+            if let Some((key, value)) = extra {
+                deps.insert(key.to_string(), value);
+            }
+
+            let mut manifest = Table::new();
+            manifest.insert("package".into(), Value::Table(package));
+            let target = Value::Table(target);
+            manifest.insert(
+                target_key.into(),
+                if target_key == "bin" { Value::Array(vec![target]) } else { target },
+            );
+            manifest.insert("dependencies".into(), Value::Table(deps));
+            if !targets.is_empty() {
+                manifest.insert("target".into(), Value::Table(targets.clone()));
+            }
+            toml::to_string(&manifest)
+        };
+
+        let mut lib = Table::new();
+        lib.insert("name".into(), PREAMBLE_CRATE.into());
+        lib.insert("path".into(), "lib.rs".into());
+        let mut bin = Table::new();
+        bin.insert("name".into(), self.bin.clone().into());
+        bin.insert("path".into(), "main.rs".into());
+
+        Ok([
+            toml::to_string(&root)?,
+            crate_manifest("rsh-preamble", "lib", lib, None)?,
+            crate_manifest("rsh-body", "bin", bin, Some(("rsh-preamble", path_dependency(&self.dir.join("preamble")))))?,
+        ])
+    }
+
+    /// Copy host dependencies, making `path` dependencies absolute.
+    fn copy_dependencies(&self, deps: &Table, out: &mut Table) -> Result<(), Box<dyn Error>> {
+        for (key, value) in deps {
+            let mut value = value.clone();
+            if let Some(table) = value.as_table_mut() {
+                if table.get("workspace").and_then(Value::as_bool) == Some(true) {
+                    return Err(format!(
+                        "dependency `{key}` is inherited from a workspace, which the split layout doesn't support"
+                    )
+                    .into());
+                }
+                if let Some(path) = table.get("path").and_then(Value::as_str) {
+                    let path = self.project_dir.join(path);
+                    table.insert("path".into(), path.to_string_lossy().into_owned().into());
+                }
+            }
+            out.entry(key.clone()).or_insert(value);
+        }
+        Ok(())
+    }
+}
+
+fn path_dependency(path: &Path) -> Value {
+    let mut table = Table::new();
+    table.insert("path".into(), path.to_string_lossy().into_owned().into());
+    Value::Table(table)
+}
+
+/// What a `{` opened in the PREAMBLE belongs to.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Scope {
+    Module,
+    Struct,
+    InherentImpl,
+    Other,
+}
+
+/// Make PREAMBLE items usable from another crate: items at module scope, methods
+/// of inherent impls and struct fields get `pub`, and `macro_rules!` macros are
+/// exported. Like the PREAMBLE/BODY split itself this is line based: one item or
+/// field per line, or a struct whose fields all sit on its first line.
+pub fn publicize(preamble: &[String]) -> String {
+    let mut scopes: Vec<Scope> = Vec::new();
+    let mut out = String::new();
+
+    for line in preamble {
+        let trimmed = line.trim_start();
+        let indent = &line[..line.len() - trimmed.len()];
+        let scope = scopes.last().copied().unwrap_or(Scope::Module);
+
+        let rewritten = match scope {
+            Scope::Module if trimmed.starts_with("macro_rules!") => {
+                format!("{indent}#[macro_export]\n{line}")
+            }
+            Scope::Module if is_item(trimmed, MODULE_ITEMS) => {
+                format!("{indent}pub {}", publicize_inline_fields(trimmed))
+            }
+            Scope::Module if trimmed.starts_with("struct ") || trimmed.starts_with("pub struct ") => {
+                format!("{indent}{}", publicize_inline_fields(trimmed))
+            }
+            Scope::InherentImpl if is_item(trimmed, IMPL_ITEMS) => format!("{indent}pub {trimmed}"),
+            Scope::Struct if is_field(trimmed) => format!("{indent}pub {trimmed}"),
+            _ => line.clone(),
+        };
+        out.push_str(&rewritten);
+        out.push('\n');
+
+        let opened = count_opening_braces(line);
+        let closed = count_closing_braces(line);
+        let balance = opened - closed;
+        if balance > 0 {
+            let first = match scope {
+                Scope::Module => opened_scope(trimmed),
+                _ => Scope::Other,
+            };
+            scopes.push(first);
+            for _ in 1..balance {
+                scopes.push(Scope::Other);
+            }
+        } else {
+            for _ in 0..-balance {
+                scopes.pop();
+            }
+        }
+    }
+    out
+}
+
+const MODULE_ITEMS: &[&str] = &[
+    "fn ", "async fn ", "unsafe fn ", "const fn ", "struct ", "enum ", "union ", "type ", "trait ",
+    "unsafe trait ", "const ", "static ", "mod ", "use ",
+];
+
+const IMPL_ITEMS: &[&str] = &["fn ", "async fn ", "unsafe fn ", "const fn ", "const "];
+
+fn is_item(line: &str, items: &[&str]) -> bool {
+    items.iter().any(|item| line.starts_with(item))
+}
+
+/// `name: Type,` inside a struct body.
+fn is_field(line: &str) -> bool {
+    let Some((name, _)) = line.split_once(':') else {
+        return false;
+    };
+    !name.is_empty()
+        && !line.starts_with("pub")
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+        && !line[name.len()..].starts_with("::")
+}
+
+/// The scope opened by the first `{` of a module-level line.
+fn opened_scope(line: &str) -> Scope {
+    let line = line.strip_prefix("pub ").unwrap_or(line);
+    if line.starts_with("struct ") || line.starts_with("union ") {
+        Scope::Struct
+    } else if line.starts_with("mod ") {
+        Scope::Module
+    } else if line.starts_with("impl") && !line.contains(" for ") {
+        Scope::InherentImpl
+    } else {
+        Scope::Other
+    }
+}
+
+/// Add `pub` to the fields of a struct declared on this line, e.g.
+/// `struct P { x: i32 }` or `struct P(i32, i32);`.
+fn publicize_inline_fields(line: &str) -> String {
+    let decl = line.strip_prefix("pub ").unwrap_or(line);
+    if !decl.starts_with("struct ") {
+        return line.to_string();
+    }
+    let Some(open) = line.find(['{', '(']) else {
+        return line.to_string();
+    };
+    let close = if line.as_bytes()[open] == b'{' { '}' } else { ')' };
+    let Some(end) = line.rfind(close).filter(|&end| end > open) else {
+        return line.to_string();
+    };
+
+    let fields: Vec<String> = split_top_level(&line[open + 1..end])
+        .into_iter()
+        .map(|field| {
+            let field = field.trim();
+            if field.is_empty() || field.starts_with("pub") || field.starts_with('#') {
+                field.to_string()
+            } else {
+                format!("pub {field}")
+            }
+        })
+        .collect();
+    let fields = fields.join(", ");
+    let fields = if close == '}' && !fields.is_empty() { format!(" {} ", fields.trim_end_matches(", ")) } else { fields };
+    format!("{}{}{}", &line[..=open], fields, &line[end..])
+}
+
+/// Split on commas that are not nested in brackets or generics.
+fn split_top_level(s: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0i32;
+    let mut start = 0;
+    let mut prev = ' ';
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' | '[' | '{' | '<' => depth += 1,
+            // `->` in a function pointer type is not a closing generic.
+            '>' if prev == '-' => {}
+            ')' | ']' | '}' | '>' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+        prev = c;
+    }
+    parts.push(&s[start..]);
+    parts
+}
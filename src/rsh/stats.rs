@@ -0,0 +1,78 @@
+use std::time::Duration;
+
+use super::session::RunOutcome;
+
+/// Timings of one submitted block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockTiming {
+    /// First line of the block, as typed.
+    pub first_line: String,
+    /// Time spent in cargo, including an async auto-switch rebuild.
+    pub build: Duration,
+    /// `true` when the executable came from the build cache.
+    pub cached: bool,
+    /// Time the program ran; `None` when it didn't compile.
+    pub run: Option<Duration>,
+    pub outcome: RunOutcome,
+}
+
+/// Per-block and cumulative timings for a session, shown by `:stats`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SessionStats {
+    pub blocks: Vec<BlockTiming>,
+}
+
+impl SessionStats {
+    pub fn record(&mut self, timing: BlockTiming) {
+        self.blocks.push(timing);
+    }
+
+    pub fn total_build(&self) -> Duration {
+        self.blocks.iter().map(|b| b.build).sum()
+    }
+
+    pub fn total_run(&self) -> Duration {
+        self.blocks.iter().filter_map(|b| b.run).sum()
+    }
+
+    pub fn print(&self) {
+        if self.blocks.is_empty() {
+            println!("No blocks run yet.");
+            return;
+        }
+        let ok = self.blocks.iter().filter(|b| b.outcome == RunOutcome::Success).count();
+        let cached = self.blocks.iter().filter(|b| b.cached).count();
+        println!(
+            "blocks: {} ({} ok, {} failed), cached builds: {}",
+            self.blocks.len(),
+            ok,
+            self.blocks.len() - ok,
+            cached
+        );
+        println!(
+            "build: {:.2}s total, run: {:.2}s total",
+            self.total_build().as_secs_f64(),
+            self.total_run().as_secs_f64()
+        );
+        println!("{:>4}  {:>8}  {:>8}  {:<12}  block", "#", "build", "run", "outcome");
+        for (index, block) in self.blocks.iter().enumerate() {
+            let build = if block.cached {
+                "cached".to_string()
+            } else {
+                format!("{:.2}s", block.build.as_secs_f64())
+            };
+            let run = block
+                .run
+                .map(|run| format!("{:.2}s", run.as_secs_f64()))
+                .unwrap_or_else(|| "-".to_string());
+            println!(
+                "{:>4}  {:>8}  {:>8}  {:<12}  {}",
+                index + 1,
+                build,
+                run,
+                block.outcome.to_string(),
+                block.first_line
+            );
+        }
+    }
+}
@@ -0,0 +1,277 @@
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use toml::{Table, Value};
+
+use super::lock::STATE_DIR;
+use super::session::{FailurePolicy, Mode, ModeRequest, RunSettings};
+use super::split::Layout;
+
+/// Autosaves kept per project; older ones are removed as new sessions start saving.
+const MAX_AUTOSAVES: usize = 8;
+
+/// The state of a session that survives a restart: buffers, mode, layout, run
+/// settings and failure policies. Stored as TOML:
+///
+/// ```toml
+/// mode = "async tokio current_thread"
+/// layout = "single"
+/// preamble = ["use std::collections::HashMap;"]
+/// body = ["let mut seen = HashMap::new();"]
+///
+/// [on-failure]
+/// compile = "rollback"
+/// runtime = "keep"
+///
+/// [run]
+/// args = ["--verbose"]
+/// cwd = "/path/to/project/data"
+/// unset = ["RUST_LOG"]
+///
+/// [run.env]
+/// API_URL = "http://localhost:8080"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavedSession {
+    pub preamble: Vec<String>,
+    pub body: Vec<String>,
+    /// Resolved again on restore, so a runtime removed from Cargo.toml is caught.
+    pub mode: ModeRequest,
+    pub layout: Layout,
+    pub settings: RunSettings,
+    pub on_compile_failure: FailurePolicy,
+    pub on_runtime_failure: FailurePolicy,
+}
+
+impl SavedSession {
+    pub fn to_toml(&self) -> String {
+        let strings = |items: &[String]| Value::Array(items.iter().cloned().map(Value::String).collect());
+
+        let mut table = Table::new();
+        table.insert("mode".into(), Value::String(self.mode.to_string()));
+        table.insert("layout".into(), Value::String(self.layout.to_string()));
+        table.insert("preamble".into(), strings(&self.preamble));
+        table.insert("body".into(), strings(&self.body));
+
+        let mut policies = Table::new();
+        policies.insert("compile".into(), Value::String(self.on_compile_failure.to_string()));
+        policies.insert("runtime".into(), Value::String(self.on_runtime_failure.to_string()));
+        table.insert("on-failure".into(), Value::Table(policies));
+
+        let settings = &self.settings;
+        let mut run = Table::new();
+        run.insert("args".into(), strings(&settings.args));
+        if let Some(cwd) = &settings.cwd {
+            run.insert("cwd".into(), Value::String(cwd.to_string_lossy().into_owned()));
+        }
+        if let Some(stdin) = &settings.stdin {
+            run.insert("stdin".into(), Value::String(stdin.to_string_lossy().into_owned()));
+        }
+        let unset: Vec<String> = settings.env.iter().filter(|(_, v)| v.is_none()).map(|(k, _)| k.clone()).collect();
+        run.insert("unset".into(), strings(&unset));
+        let env: Table = settings
+            .env
+            .iter()
+            .filter_map(|(key, value)| Some((key.clone(), Value::String(value.clone()?))))
+            .collect();
+        run.insert("env".into(), Value::Table(env));
+        table.insert("run".into(), Value::Table(run));
+
+        table.to_string()
+    }
+
+    pub fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        let table: Table = text.parse()?;
+        let string = |table: &Table, key: &str| table.get(key).and_then(Value::as_str).map(str::to_string);
+        let strings = |table: &Table, key: &str| -> Result<Vec<String>, String> {
+            let Some(value) = table.get(key) else {
+                return Ok(Vec::new());
+            };
+            value
+                .as_array()
+                .and_then(|items| items.iter().map(|v| v.as_str().map(str::to_string)).collect())
+                .ok_or_else(|| format!("`{key}` must be an array of strings"))
+        };
+
+        let mode = string(&table, "mode").unwrap_or_else(|| "sync".to_string());
+        let words: Vec<&str> = mode.split_whitespace().collect();
+        let mode = ModeRequest::parse(&words).map_err(|e| format!("invalid `mode`: {e}"))?;
+        let layout = match string(&table, "layout") {
+            Some(layout) => Layout::parse(&layout).ok_or_else(|| format!("invalid `layout`: {layout}"))?,
+            None => Layout::Single,
+        };
+
+        let empty = Table::new();
+        let policies = table.get("on-failure").and_then(Value::as_table).unwrap_or(&empty);
+        let policy = |key: &str| -> Result<FailurePolicy, String> {
+            match string(policies, key) {
+                Some(policy) => FailurePolicy::parse(&policy).ok_or_else(|| format!("invalid `on-failure.{key}`: {policy}")),
+                None => Ok(FailurePolicy::Rollback),
+            }
+        };
+
+        let run = table.get("run").and_then(Value::as_table).unwrap_or(&empty);
+        let mut settings = RunSettings {
+            args: strings(run, "args")?,
+            cwd: string(run, "cwd").map(PathBuf::from),
+            stdin: string(run, "stdin").map(PathBuf::from),
+            ..RunSettings::default()
+        };
+        for key in strings(run, "unset")? {
+            settings.env.insert(key, None);
+        }
+        if let Some(env) = run.get("env").and_then(Value::as_table) {
+            for (key, value) in env {
+                let value = value.as_str().ok_or_else(|| format!("`run.env.{key}` must be a string"))?;
+                settings.env.insert(key.clone(), Some(value.to_string()));
+            }
+        }
+
+        Ok(SavedSession {
+            preamble: strings(&table, "preamble")?,
+            body: strings(&table, "body")?,
+            mode,
+            layout,
+            settings,
+            on_compile_failure: policy("compile")?,
+            on_runtime_failure: policy("runtime")?,
+        })
+    }
+}
+
+/// The request that resolves back to `mode`.
+pub fn mode_request(mode: &Mode) -> ModeRequest {
+    match mode {
+        Mode::Sync => ModeRequest::Sync,
+        Mode::Async(runtime) => ModeRequest::Async {
+            runtime: Some(runtime.name().to_string()),
+            flavor: runtime.flavor().map(str::to_string),
+        },
+    }
+}
+
+/// A named session in the store.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredSession {
+    pub name: String,
+    pub path: PathBuf,
+    pub modified: SystemTime,
+}
+
+/// Saved sessions of a project: named ones in `.rsh/sessions/<name>.toml`, and
+/// autosaves in `.rsh/autosave/`, one file per rsh process.
+#[derive(Debug, Clone)]
+pub struct SessionStore {
+    dir: PathBuf,
+}
+
+impl SessionStore {
+    pub fn new<P: AsRef<Path>>(project_dir: P) -> Self {
+        SessionStore {
+            dir: project_dir.as_ref().join(STATE_DIR),
+        }
+    }
+
+    /// Save `session` as `name`, replacing an earlier session of that name.
+    pub fn save(&self, name: &str, session: &SavedSession) -> Result<PathBuf, Box<dyn Error>> {
+        check_name(name)?;
+        let path = self.named_path(name);
+        write_atomically(&path, &session.to_toml())?;
+        Ok(path)
+    }
+
+    pub fn open(&self, name: &str) -> Result<SavedSession, Box<dyn Error>> {
+        check_name(name)?;
+        let path = self.named_path(name);
+        if !path.is_file() {
+            return Err(format!("no saved session named '{name}'").into());
+        }
+        load(&path)
+    }
+
+    /// Named sessions, most recently saved first.
+    pub fn list(&self) -> Vec<StoredSession> {
+        let mut sessions: Vec<StoredSession> = toml_files(&self.dir.join("sessions"))
+            .into_iter()
+            .filter_map(|(path, modified)| {
+                let name = path.file_stem()?.to_str()?.to_string();
+                Some(StoredSession { name, path, modified })
+            })
+            .collect();
+        sessions.sort_by(|a, b| b.modified.cmp(&a.modified).then_with(|| a.name.cmp(&b.name)));
+        sessions
+    }
+
+    /// A fresh autosave file for a session started now by this process.
+    pub fn autosave_path(&self) -> PathBuf {
+        let started = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+        self.dir.join("autosave").join(format!("{started}-{}.toml", std::process::id()))
+    }
+
+    /// Write `session` to `path`, then drop the oldest autosaves beyond the limit.
+    pub fn autosave(&self, path: &Path, session: &SavedSession) -> Result<(), Box<dyn Error>> {
+        write_atomically(path, &session.to_toml())?;
+        let mut autosaves = toml_files(&self.dir.join("autosave"));
+        autosaves.sort_by_key(|(_, modified)| std::cmp::Reverse(*modified));
+        for (old, _) in autosaves.iter().skip(MAX_AUTOSAVES) {
+            let _ = fs::remove_file(old);
+        }
+        Ok(())
+    }
+
+    /// The most recently written autosave other than `except`.
+    pub fn latest_autosave(&self, except: Option<&Path>) -> Option<PathBuf> {
+        toml_files(&self.dir.join("autosave"))
+            .into_iter()
+            .filter(|(path, _)| Some(path.as_path()) != except)
+            .max_by_key(|(_, modified)| *modified)
+            .map(|(path, _)| path)
+    }
+
+    fn named_path(&self, name: &str) -> PathBuf {
+        self.dir.join("sessions").join(format!("{name}.toml"))
+    }
+}
+
+pub fn load(path: &Path) -> Result<SavedSession, Box<dyn Error>> {
+    let text = fs::read_to_string(path)?;
+    SavedSession::parse(&text).map_err(|e| format!("{}: {}", path.display(), e).into())
+}
+
+/// Session names become file names, so they are kept to letters, digits, `-` and `_`.
+fn check_name(name: &str) -> Result<(), String> {
+    if !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        Ok(())
+    } else {
+        Err(format!("invalid session name '{name}'; use letters, digits, '-' and '_'"))
+    }
+}
+
+/// Write through a temporary file and rename, so a crash mid-write never leaves
+/// a truncated session behind.
+fn write_atomically(path: &Path, text: &str) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("toml.tmp");
+    fs::write(&tmp, text)?;
+    fs::rename(&tmp, path)
+}
+
+fn toml_files(dir: &Path) -> Vec<(PathBuf, SystemTime)> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+        .filter_map(|path| {
+            let modified = fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+            Some((path, modified))
+        })
+        .collect()
+}
@@ -0,0 +1,134 @@
+use std::fmt;
+
+/// Shape of the generated program in sync mode.
+pub const SYNC_TEMPLATE: &str = "\
+{preamble}
+fn __rsh_session() -> {error_type} {
+    {init}
+    {body}
+    std::result::Result::Ok(())
+}
+
+{main}
+";
+
+/// Shape of the generated program in async mode.
+pub const ASYNC_TEMPLATE: &str = "\
+{preamble}
+async fn __rsh_session() -> {error_type} {
+    {init}
+    {body}
+    std::result::Result::Ok(())
+}
+
+{main}
+";
+
+/// Placeholders that must stand alone on a line; they expand to several lines
+/// at that line's indentation.
+const LINE_PLACEHOLDERS: [&str; 4] = ["{preamble}", "{init}", "{body}", "{main}"];
+
+/// A code generation template, from `[templates]` in the config or the defaults.
+///
+/// A line holding only `{preamble}`, `{init}`, `{body}` or `{main}` is replaced
+/// by the prelude and PREAMBLE, the logger setup (if any), the BODY, or the
+/// `fn main` that runs `__rsh_session` (under the async runtime, in async mode).
+/// `{error_type}` and `{runtime}` are replaced anywhere in a line; any other
+/// braces are left alone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    text: String,
+}
+
+/// Which part of the session a line of the generated program came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineOrigin {
+    /// The template itself, or code rsh adds around the session.
+    Template,
+    /// The `{preamble}` line at this index.
+    Preamble(usize),
+    Init,
+    /// The BODY line at this index.
+    Body(usize),
+    Main,
+}
+
+/// What a template is filled with.
+pub struct TemplateContext<'a> {
+    /// Module-scope lines, blank separator lines included.
+    pub preamble: &'a [String],
+    /// Statements that run before the BODY, e.g. installing a logger.
+    pub init: &'a [String],
+    pub body: &'a [String],
+    pub main: &'a str,
+    pub error_type: &'a str,
+    /// Name of the async runtime; empty in sync mode.
+    pub runtime: &'a str,
+}
+
+impl Template {
+    /// Check that `text` has `{preamble}`, `{body}` and `{main}` lines: without
+    /// them the session's code would silently go missing, or never run.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        for required in ["{preamble}", "{body}", "{main}"] {
+            if !text.lines().any(|line| line.trim() == required) {
+                return Err(format!("template has no line holding only {required}"));
+            }
+        }
+        Ok(Template { text: text.to_string() })
+    }
+
+    /// Whether the template has an `{init}` line for the logger setup.
+    pub fn has_init(&self) -> bool {
+        self.text.lines().any(|line| line.trim() == "{init}")
+    }
+
+    pub fn sync() -> Self {
+        Template { text: SYNC_TEMPLATE.to_string() }
+    }
+
+    pub fn async_() -> Self {
+        Template { text: ASYNC_TEMPLATE.to_string() }
+    }
+
+    pub fn render(&self, ctx: &TemplateContext) -> String {
+        self.render_mapped(ctx).0
+    }
+
+    /// Render, along with the origin of each line of the result.
+    pub fn render_mapped(&self, ctx: &TemplateContext) -> (String, Vec<LineOrigin>) {
+        let mut code = String::new();
+        let mut origins = Vec::new();
+        for line in self.text.lines() {
+            let trimmed = line.trim();
+            if !LINE_PLACEHOLDERS.contains(&trimmed) {
+                code.push_str(&line.replace("{error_type}", ctx.error_type).replace("{runtime}", ctx.runtime));
+                code.push('\n');
+                origins.push(LineOrigin::Template);
+                continue;
+            }
+            let indent = &line[..line.len() - line.trim_start().len()];
+            let lines: Vec<(&str, LineOrigin)> = match trimmed {
+                "{preamble}" => ctx.preamble.iter().enumerate().map(|(i, l)| (l.as_str(), LineOrigin::Preamble(i))).collect(),
+                "{init}" => ctx.init.iter().map(|l| (l.as_str(), LineOrigin::Init)).collect(),
+                "{body}" => ctx.body.iter().enumerate().map(|(i, l)| (l.as_str(), LineOrigin::Body(i))).collect(),
+                _ => ctx.main.lines().map(|l| (l, LineOrigin::Main)).collect(),
+            };
+            for (expanded, origin) in lines {
+                if !expanded.is_empty() {
+                    code.push_str(indent);
+                    code.push_str(expanded);
+                }
+                code.push('\n');
+                origins.push(origin);
+            }
+        }
+        (code, origins)
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
@@ -1,54 +1,452 @@
 use std::error::Error;
 use std::fs;
-use std::path::Path;
-use std::process::Command;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use super::session::AsyncRuntime;
+use serde_json::Value as Json;
 
+use super::cargo::{cargo_command, CargoOptions};
+use super::progress::Progress;
+use super::session::RunSettings;
 
-pub fn run_cargo_rsh<P: AsRef<Path>>(dir: P) -> Result<std::process::Output, Box<dyn Error>> {
-    let output = Command::new("cargo")
-        .arg("run")
+
+/// Result of building the generated `__rsh` binary.
+#[derive(Debug, Clone)]
+pub struct BuildOutput {
+    pub status: ExitStatus,
+    /// Compiler diagnostics as rustc renders them, followed by cargo's own messages.
+    pub stderr: String,
+    /// The compiler diagnostics, in the order their rendered text appears in `stderr`.
+    pub diagnostics: Vec<Diagnostic>,
+    /// The built executable, taken from cargo's `compiler-artifact` message.
+    pub executable: Option<PathBuf>,
+    pub elapsed: Duration,
+    /// Part of `elapsed` spent compiling the PREAMBLE crate in the split layout,
+    /// when it had to be rebuilt.
+    pub preamble_elapsed: Option<Duration>,
+    /// `true` when the executable came from the build cache and cargo wasn't run.
+    pub cached: bool,
+}
+
+impl BuildOutput {
+    pub fn success(&self) -> bool {
+        self.status.success() && self.executable.is_some()
+    }
+
+    /// Cargo's own messages: `stderr` after the rendered diagnostics.
+    pub fn cargo_stderr(&self) -> &str {
+        let rendered: usize = self.diagnostics.iter().map(|d| d.rendered.len()).sum();
+        self.stderr.get(rendered..).unwrap_or("")
+    }
+}
+
+/// A compiler diagnostic from cargo's `compiler-message` entries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// `error`, `warning`, `note`, ….
+    pub level: String,
+    pub message: String,
+    pub rendered: String,
+    /// File and 1-based line of the primary span, relative to the package root;
+    /// `None` for diagnostics without a location, like `3 warnings emitted`.
+    pub file: Option<PathBuf>,
+    pub line: Option<usize>,
+}
+
+impl Diagnostic {
+    fn from_json(message: &Json) -> Option<Self> {
+        let rendered = message["rendered"].as_str()?.to_string();
+        let primary = message["spans"]
+            .as_array()
+            .and_then(|spans| spans.iter().find(|span| span["is_primary"] == true));
+        Some(Diagnostic {
+            level: message["level"].as_str().unwrap_or("").to_string(),
+            message: message["message"].as_str().unwrap_or("").to_string(),
+            rendered,
+            file: primary.and_then(|span| span["file_name"].as_str()).map(PathBuf::from),
+            line: primary.and_then(|span| span["line_start"].as_u64()).map(|line| line as usize),
+        })
+    }
+}
+
+/// How the generated program ran.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunReport {
+    pub status: ExitStatus,
+    pub elapsed: Duration,
+    /// Peak resident set size in bytes, where the platform reports it.
+    pub peak_rss: Option<u64>,
+}
+
+impl RunReport {
+    /// The signal that terminated the program, if any.
+    pub fn signal(&self) -> Option<i32> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+            self.status.signal()
+        }
+        #[cfg(not(unix))]
+        {
+            None
+        }
+    }
+}
+
+/// Build the generated `__rsh` binary without running it.
+///
+/// Cargo reports in JSON so the executable path can be read from the
+/// `compiler-artifact` message; the human-readable diagnostics are collected from
+/// the `compiler-message` entries.
+pub fn build_cargo_rsh<P: AsRef<Path>>(dir: P, target: &str, options: &CargoOptions) -> Result<BuildOutput, Box<dyn Error>> {
+    cargo_build(cargo_rsh_command(dir, target, options), target)
+}
+
+/// `cargo build --bin <target>` for the project in `dir`, without output options.
+pub fn cargo_rsh_command<P: AsRef<Path>>(dir: P, target: &str, options: &CargoOptions) -> Command {
+    let mut cmd = cargo_command("build", dir, options);
+    cmd.arg("--bin").arg(target);
+    cmd
+}
+
+/// Run a prepared `cargo build` command with JSON messages and collect its result;
+/// the executable is the one of the binary target named `target`.
+///
+/// Messages are read as cargo emits them, so a [`Progress`] indicator can show the
+/// elapsed time and the crates finished so far while a long build runs.
+pub fn cargo_build(mut cmd: Command, target: &str) -> Result<BuildOutput, Box<dyn Error>> {
+    let started = Instant::now();
+    // With `--color=always` (see `CargoOptions::color`), rendered diagnostics keep their colors.
+    let colored = cmd.get_args().any(|arg| arg == "--color=always");
+    let format = if colored { "--message-format=json-diagnostic-rendered-ansi" } else { "--message-format=json" };
+    let mut child = cmd
         .arg("--quiet")
-        .arg("--bin")
-        .arg("__rsh")
-        .current_dir(dir)
-        .output()?;
-    Ok(output)
+        .arg(format)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let stdout = child.stdout.take().ok_or("cargo stdout not captured")?;
+    let mut cargo_stderr = child.stderr.take().ok_or("cargo stderr not captured")?;
+    let stderr_reader = thread::spawn(move || {
+        let mut text = String::new();
+        let _ = cargo_stderr.read_to_string(&mut text);
+        text
+    });
+    let (lines, messages) = mpsc::channel();
+    thread::spawn(move || {
+        for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+            if lines.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut progress = Progress::new(started);
+    let mut stderr = String::new();
+    let mut diagnostics = Vec::new();
+    let mut executable = None;
+    loop {
+        let line = match messages.recv_timeout(Duration::from_millis(100)) {
+            Ok(line) => line,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                progress.draw();
+                continue;
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
+        let Ok(message) = serde_json::from_str::<Json>(&line) else {
+            continue;
+        };
+        match message["reason"].as_str() {
+            Some("compiler-message") => {
+                if let Some(diagnostic) = Diagnostic::from_json(&message["message"]) {
+                    stderr.push_str(&diagnostic.rendered);
+                    diagnostics.push(diagnostic);
+                }
+            }
+            Some("compiler-artifact") => {
+                if message["target"]["name"] == target {
+                    executable = message["executable"].as_str().map(PathBuf::from);
+                } else if let Some(name) = message["target"]["name"].as_str() {
+                    progress.crate_done(name);
+                }
+            }
+            _ => {}
+        }
+        progress.draw();
+    }
+    progress.clear();
+
+    let status = child.wait()?;
+    let elapsed = started.elapsed();
+    stderr.push_str(&stderr_reader.join().unwrap_or_default());
+
+    Ok(BuildOutput {
+        status,
+        stderr,
+        diagnostics,
+        executable,
+        elapsed,
+        preamble_elapsed: None,
+        cached: false,
+    })
 }
 
-pub fn looks_like_async_error(stderr: &str) -> bool {
-    let patterns = [
-        "E0728",
-        "E0752",
-        "only allowed inside `async` functions",
-        "only allowed inside async functions",
-        "cannot be used in a `fn` item that is not `async`",
-        "future cannot be sent between threads safely",
-        "cannot be sent between threads safely",
-        "async fn main",
-    ];
-
-    patterns.iter().any(|p| stderr.contains(p))
-}
-
-pub fn detect_async_runtime<P: AsRef<Path>>(cargo_path: P) -> Option<AsyncRuntime> {
-    let Ok(toml) = fs::read_to_string(cargo_path) else {
-        eprintln!("rsh: Error loading Cargo.toml for detecting async runtime.");
-        return None;
+/// Format Rust source with `rustfmt`, passing it through stdin and stdout.
+/// `Err` carries rustfmt's message, e.g. for code that doesn't parse.
+pub fn rustfmt(source: &str, edition: &str) -> Result<String, String> {
+    let mut child = Command::new("rustfmt")
+        .arg("--edition")
+        .arg(edition)
+        .arg("--emit")
+        .arg("stdout")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("could not run rustfmt: {e}"))?;
+
+    let mut stdin = child.stdin.take().ok_or("rustfmt stdin not captured")?;
+    let input = source.to_string();
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child.wait_with_output().map_err(|e| format!("rustfmt failed: {e}"))?;
+    let _ = writer.join();
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim_end().to_string());
+    }
+    String::from_utf8(output.stdout).map_err(|e| format!("rustfmt output is not UTF-8: {e}"))
+}
+
+/// For each line of `source`, whether it starts inside a string literal (one
+/// that spans lines). Those lines are part of the literal's value, so
+/// reindenting them would change what the program does.
+pub fn lines_in_string_literals(source: &str) -> Vec<bool> {
+    enum State {
+        Code,
+        Str,
+        RawStr(usize),
+        BlockComment(usize),
+    }
+
+    let chars: Vec<char> = source.chars().collect();
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+    let mut flags = vec![false];
+    let mut state = State::Code;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '\n' {
+            flags.push(matches!(state, State::Str | State::RawStr(_)));
+            i += 1;
+            continue;
+        }
+        match state {
+            State::Code => match c {
+                '/' if chars.get(i + 1) == Some(&'/') => {
+                    while i < chars.len() && chars[i] != '\n' {
+                        i += 1;
+                    }
+                    continue;
+                }
+                '/' if chars.get(i + 1) == Some(&'*') => {
+                    state = State::BlockComment(1);
+                    i += 1;
+                }
+                '"' => state = State::Str,
+                'r' if i == 0 || !is_ident(chars[i - 1]) || (matches!(chars[i - 1], 'b' | 'c') && (i < 2 || !is_ident(chars[i - 2]))) => {
+                    let hashes = chars[i + 1..].iter().take_while(|&&c| c == '#').count();
+                    if chars.get(i + 1 + hashes) == Some(&'"') {
+                        state = State::RawStr(hashes);
+                        i += hashes + 1;
+                    }
+                }
+                '\'' if chars.get(i + 1) == Some(&'\\') => {
+                    i += 2;
+                    while i + 1 < chars.len() && chars[i + 1] != '\'' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+                // A char literal; anything else is a lifetime or label.
+                '\'' if chars.get(i + 2) == Some(&'\'') => i += 2,
+                _ => {}
+            },
+            State::Str => match c {
+                '\\' => i += 1,
+                '"' => state = State::Code,
+                _ => {}
+            },
+            State::RawStr(hashes) => {
+                if c == '"' && chars[i + 1..].iter().take(hashes).filter(|&&c| c == '#').count() == hashes {
+                    state = State::Code;
+                    i += hashes;
+                }
+            }
+            State::BlockComment(depth) => {
+                if c == '/' && chars.get(i + 1) == Some(&'*') {
+                    state = State::BlockComment(depth + 1);
+                    i += 1;
+                } else if c == '*' && chars.get(i + 1) == Some(&'/') {
+                    state = if depth == 1 { State::Code } else { State::BlockComment(depth - 1) };
+                    i += 1;
+                }
+            }
+        }
+        i += 1;
+    }
+    flags
+}
+
+/// The project's cargo target directory (`$CARGO_TARGET_DIR` or `target/`).
+pub fn target_dir<P: AsRef<Path>>(project_dir: P) -> PathBuf {
+    let project_dir = project_dir.as_ref();
+    std::env::var_os("CARGO_TARGET_DIR")
+        .map(|dir| project_dir.join(dir))
+        .unwrap_or_else(|| project_dir.join("target"))
+}
+
+/// Write `contents` to `path` unless it already holds exactly that, so an unchanged
+/// file keeps its mtime and cargo's fingerprints stay fresh. Returns whether it wrote.
+pub fn write_if_changed<P: AsRef<Path>>(path: P, contents: &str) -> io::Result<bool> {
+    let path = path.as_ref();
+    if fs::read_to_string(path).is_ok_and(|existing| existing == contents) {
+        return Ok(false);
+    }
+    fs::write(path, contents)?;
+    Ok(true)
+}
+
+/// Run the executable built by [`build_cargo_rsh`] directly, with the terminal's
+/// stdout and stderr.
+///
+/// The program starts in the working directory chosen by `settings` (the project
+/// root `dir` by default) and reads stdin from the `:stdin` file, or an empty stdin.
+pub fn run_rsh_binary<P: AsRef<Path>>(
+    executable: &Path,
+    dir: P,
+    settings: &RunSettings,
+) -> Result<RunReport, Box<dyn Error>> {
+    let dir = dir.as_ref();
+    let mut cmd = Command::new(executable);
+    cmd.args(&settings.args)
+        .current_dir(settings.cwd.as_deref().unwrap_or(dir));
+
+    for (key, value) in &settings.env {
+        match value {
+            Some(v) => cmd.env(key, v),
+            None => cmd.env_remove(key),
+        };
+    }
+
+    match &settings.stdin {
+        Some(file) => cmd.stdin(Stdio::from(fs::File::open(file)?)),
+        None => cmd.stdin(Stdio::null()),
     };
-    let lower = toml.to_lowercase();
 
-    if lower.contains("tokio") {
-        return Some(AsyncRuntime::Tokio);
+    let started = Instant::now();
+    let child = cmd.spawn()?;
+    let (status, peak_rss) = wait_with_peak_rss(child)?;
+    Ok(RunReport {
+        status,
+        elapsed: started.elapsed(),
+        peak_rss,
+    })
+}
+
+/// Wait for `child` and read its peak RSS from the rusage `wait4` reports.
+#[cfg(unix)]
+fn wait_with_peak_rss(child: Child) -> io::Result<(ExitStatus, Option<u64>)> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let pid = child.id() as libc::pid_t;
+    let mut status = 0;
+    // SAFETY: rusage is plain old data, so all-zero is a valid value.
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    loop {
+        // SAFETY: `pid` is our own unreaped child, and both out-pointers are valid.
+        let ret = unsafe { libc::wait4(pid, &mut status, 0, &mut usage) };
+        if ret == pid {
+            break;
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
     }
-    if lower.contains("async-std") {
-        return Some(AsyncRuntime::AsyncStd);
+
+    // Linux reports kilobytes, macOS bytes.
+    let max_rss = usage.ru_maxrss as u64;
+    let peak_rss = if cfg!(target_os = "macos") { max_rss } else { max_rss * 1024 };
+    Ok((ExitStatus::from_raw(status), Some(peak_rss)))
+}
+
+#[cfg(not(unix))]
+fn wait_with_peak_rss(mut child: Child) -> io::Result<(ExitStatus, Option<u64>)> {
+    Ok((child.wait()?, None))
+}
+
+/// One line summarising compile and run, e.g.
+/// `build 1.42s | run 0.01s, exit status 0, peak RSS 1.9 MiB`.
+pub fn format_run_summary(build: &BuildOutput, run: &RunReport) -> String {
+    let build = match (build.cached, build.preamble_elapsed) {
+        (true, _) => "build cached".to_string(),
+        (false, Some(preamble)) => format!(
+            "build {:.2}s (preamble {:.2}s, body {:.2}s)",
+            build.elapsed.as_secs_f64(),
+            preamble.as_secs_f64(),
+            build.elapsed.saturating_sub(preamble).as_secs_f64()
+        ),
+        (false, None) => format!("build {:.2}s", build.elapsed.as_secs_f64()),
+    };
+    let mut summary = format!("{} | run {:.2}s, ", build, run.elapsed.as_secs_f64());
+    match (run.status.code(), run.signal()) {
+        (Some(code), _) => summary.push_str(&format!("exit status {code}")),
+        (None, Some(signal)) => summary.push_str(&format!("terminated by signal {signal}")),
+        (None, None) => summary.push_str("no exit status"),
     }
-    if lower.contains("smol") {
-        return Some(AsyncRuntime::Smol);
+    if let Some(bytes) = run.peak_rss {
+        summary.push_str(&format!(", peak RSS {:.1} MiB", bytes as f64 / (1024.0 * 1024.0)));
     }
+    summary
+}
 
-    None
+/// Whether compiler output reports an error that means the code needs async mode:
+/// E0728 (`await` outside of an async function) or E0752 (async `main`).
+pub fn looks_like_async_error(stderr: &str) -> bool {
+    error_codes(stderr).iter().any(|code| code == "E0728" || code == "E0752")
 }
 
+/// The error codes of all `error[EXXXX]` diagnostics in rustc's human-readable output.
+pub fn error_codes(stderr: &str) -> Vec<String> {
+    strip_ansi(stderr)
+        .lines()
+        .filter_map(|line| line.strip_prefix("error["))
+        .filter_map(|rest| rest.split_once(']'))
+        .map(|(code, _)| code.to_string())
+        .collect()
+}
+
+/// `text` without ANSI color sequences (`ESC [ ... m`), for reading colored diagnostics.
+pub fn strip_ansi(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Skip up to and including the final byte of the sequence.
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            plain.push(c);
+        }
+    }
+    plain
+}
@@ -0,0 +1,59 @@
+use rsh::rsh::{body_uses_async, Mode, Session};
+use rsh::rsh::utils::{error_codes, looks_like_async_error};
+
+mod common;
+use common::TestProject;
+
+fn body(code: &str) -> Vec<String> {
+    code.lines().map(str::to_string).collect()
+}
+
+#[test]
+fn test_detects_await_and_async_blocks() {
+    assert!(body_uses_async(&body("let x = fetch().await;")));
+    assert!(body_uses_async(&body("let x = fetch()\n    .await?;")));
+    assert!(body_uses_async(&body("let fut = async { 1 };")));
+    assert!(body_uses_async(&body("let fut = async move { x };")));
+    assert!(body_uses_async(&body("let f = async |x: u32| x + 1;")));
+    assert!(body_uses_async(&body("let f = async move || 1;")));
+}
+
+#[test]
+fn test_ignores_async_in_literals_comments_and_items() {
+    assert!(!body_uses_async(&body("println!(\"call .await later\");")));
+    assert!(!body_uses_async(&body("let s = r#\"async { }\"#;")));
+    assert!(!body_uses_async(&body("// x.await\nlet y = 1;")));
+    assert!(!body_uses_async(&body("/* async { /* nested */ } */ let y = 1;")));
+    assert!(!body_uses_async(&body("let c = '{'; let l: &'static str = \"\";")));
+    assert!(!body_uses_async(&body("async fn helper() {}")));
+    assert!(!body_uses_async(&body("let awaited = 1; let asynchronous = 2;")));
+}
+
+#[test]
+fn test_async_error_classification_uses_error_codes() {
+    let stderr = "error[E0728]: `await` is only allowed inside `async` functions and blocks\n";
+    assert_eq!(error_codes(stderr), vec!["E0728"]);
+    assert!(looks_like_async_error(stderr));
+    assert!(looks_like_async_error("error[E0752]: `main` function is not allowed to be `async`\n"));
+
+    // Free text that used to trigger a switch no longer does.
+    let send_error = "error[E0277]: `Rc<u8>` cannot be sent between threads safely\n";
+    assert!(!looks_like_async_error(send_error));
+    assert!(!looks_like_async_error("note: consider using async fn main\n"));
+}
+
+#[test]
+fn test_await_without_runtime_is_rejected_before_building() {
+    let project = TestProject::new("test_await_rejected_before_build")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+
+    let mut session = Session::new(Some(&project.path));
+    session.add_code_block("async fn test() {}\ntest().await;");
+    let _ = session.run();
+
+    assert!(matches!(session.mode(), Mode::Sync));
+    assert!(session.body().is_empty());
+    // Nothing was generated: the sync build was skipped entirely.
+    assert!(!project.rsh_bin_path().exists());
+}
@@ -0,0 +1,90 @@
+use rsh::rsh::{parse_args, parse_script, run_script, Input, Session};
+
+mod common;
+use common::TestProject;
+
+#[test]
+fn test_parse_script_splits_blocks_and_commands() {
+    let script = "#!/usr/bin/env -S rsh run\n:env GREETING=hi\nlet x = 1;\nlet y = 2;\n\n\n:show\nprintln!(\"{}\", x + y);";
+    let items = parse_script(script);
+    let summary: Vec<(usize, String)> = items
+        .iter()
+        .map(|item| match &item.input {
+            Input::Command(cmd) => (item.line, format!("cmd {cmd}")),
+            Input::Code(code) => (item.line, format!("code {code}")),
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            (2, "cmd :env GREETING=hi".to_string()),
+            (3, "code let x = 1;\nlet y = 2;".to_string()),
+            (7, "cmd :show".to_string()),
+            (8, "code println!(\"{}\", x + y);".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn test_run_script_stops_at_first_failure() {
+    let project = TestProject::new("test_batch_stop")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+
+    let mut session = Session::new(Some(&project.path));
+    let script = "let x = 1;\n\nassert_eq!(x, 2);\n\nlet y = x + 1;\n";
+    assert_eq!(run_script(&mut session, "test.rsh", script, false).unwrap(), 1);
+    // The failed block was rolled back and the one after it never ran.
+    assert_eq!(session.body(), ["let x = 1;"]);
+    session.cleanup();
+}
+
+#[test]
+fn test_run_script_keep_going_counts_failures() {
+    let project = TestProject::new("test_batch_keep_going")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+
+    let mut session = Session::new(Some(&project.path));
+    let script = "let x = 1;\n\n:no-such-command\nassert_eq!(x, 2);\n\nlet y = x + 1;\n\n:q\nlet z = 3;\n";
+    assert_eq!(run_script(&mut session, "test.rsh", script, true).unwrap(), 2);
+    // `:q` ends the script.
+    assert_eq!(session.body(), ["let x = 1;", "let y = x + 1;"]);
+    session.cleanup();
+}
+
+#[test]
+fn test_run_script_counts_failed_commands() {
+    let project = TestProject::new("test_batch_failed_commands")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+
+    let mut session = Session::new(Some(&project.path));
+    let script = ":stdin missing.txt
+:cwd missing
+:session open nope
+:mode async tokio
+:check { let x: u8 = \"no\"; }
+:delete body 7
+:show
+let x = 1;
+";
+    assert_eq!(run_script(&mut session, "test.rsh", script, true).unwrap(), 6);
+    assert_eq!(session.body(), ["let x = 1;"]);
+
+    // Without --keep-going the script stops at the first failed command.
+    session.reset();
+    assert_eq!(run_script(&mut session, "test.rsh", ":cwd missing
+let x = 1;
+", false).unwrap(), 1);
+    assert!(session.body().is_empty());
+    session.cleanup();
+}
+
+#[test]
+fn test_parse_args_run() {
+    let options = parse_args(["run", "check.rsh", "--keep-going"].map(String::from)).unwrap();
+    assert_eq!(options.script.as_deref(), Some(std::path::Path::new("check.rsh")));
+    assert!(options.keep_going);
+    assert!(parse_args(["run".to_string()]).is_err());
+}
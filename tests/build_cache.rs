@@ -0,0 +1,96 @@
+use rsh::rsh::{RunOutcome, Segment, Session};
+use std::fs;
+
+mod common;
+use common::TestProject;
+
+#[test]
+fn test_identical_session_reuses_cached_executable() {
+    let project = TestProject::new("test_build_cache_reuse")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+
+    let mut session = Session::new(Some(&project.path));
+    session.add_code_block("let x = 1;");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+    assert!(!session.last_build_was_cached());
+
+    let rsh_bin = project.path.join("src/bin/__rsh.rs");
+    let mtime = fs::metadata(&rsh_bin).unwrap().modified().unwrap();
+
+    session.delete(Segment::Body, &[0]);
+    session.add_code_block("let x = 1;");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+    assert!(session.last_build_was_cached());
+    assert_eq!(fs::metadata(&rsh_bin).unwrap().modified().unwrap(), mtime);
+
+    let cached = fs::read_dir(project.path.join("target/rsh-cache")).unwrap().count();
+    assert_eq!(cached, 1);
+}
+
+#[test]
+fn test_manifest_change_invalidates_cache() {
+    let project = TestProject::new("test_build_cache_manifest")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+
+    let mut session = Session::new(Some(&project.path));
+    session.add_code_block("let x = 1;");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+
+    // A change that leaves Cargo.lock valid, so the `--locked` build still runs.
+    let manifest = project.path.join("Cargo.toml");
+    let text = fs::read_to_string(&manifest).unwrap();
+    fs::write(&manifest, format!("{text}\n[features]\nextra = []\n")).unwrap();
+
+    session.delete(Segment::Body, &[0]);
+    session.add_code_block("let x = 1;");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+    assert!(!session.last_build_was_cached());
+}
+
+#[test]
+fn test_library_change_invalidates_cache() {
+    let project = TestProject::new("test_build_cache_library")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+    let lib = project.path.join("src/lib.rs");
+    fs::write(&lib, "pub fn v() -> i32 { 1 }\n").unwrap();
+
+    let mut session = Session::new(Some(&project.path));
+    session.add_code_block("assert_eq!(test_project::v(), 1);");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+
+    // The same session against changed library code must be rebuilt, not replayed.
+    fs::write(&lib, "pub fn v() -> i32 { 2 }\n").unwrap();
+    session.delete(Segment::Body, &[0]);
+    session.add_code_block("assert_eq!(test_project::v(), 1);");
+    assert_ne!(session.run().unwrap(), RunOutcome::Success);
+    assert!(!session.last_build_was_cached());
+    session.cleanup();
+}
+
+#[test]
+fn test_cargo_config_change_invalidates_cache() {
+    let project = TestProject::new("test_build_cache_config")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+    // A symlink loop is recorded, not walked.
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink("..", project.path.join("loop")).unwrap();
+        std::os::unix::fs::symlink("..", project.path.join("src/loop")).unwrap();
+    }
+
+    let mut session = Session::new(Some(&project.path));
+    session.add_code_block("let x = 1;");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+
+    fs::create_dir_all(project.path.join(".cargo")).unwrap();
+    fs::write(project.path.join(".cargo/config.toml"), "[build]\nrustflags = [\"--cfg\", \"rsh_cache_test\"]\n").unwrap();
+    session.delete(Segment::Body, &[0]);
+    session.add_code_block("let x = 1;");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+    assert!(!session.last_build_was_cached());
+    session.cleanup();
+}
@@ -0,0 +1,83 @@
+use rsh::rsh::cargo::cargo_command;
+use rsh::rsh::{CargoOptions, Config, ProjectFiles, RunOutcome, Session};
+use std::fs;
+
+mod common;
+use common::TestProject;
+
+fn args(cmd: &std::process::Command) -> Vec<String> {
+    cmd.get_args().map(|a| a.to_string_lossy().into_owned()).collect()
+}
+
+#[test]
+fn test_cargo_command_adds_guard_flags() {
+    let project = TestProject::new("test_cargo_guard_flags").with_basic_cargo_toml();
+
+    // No lock file yet: `--locked` would make cargo refuse to create one.
+    let cmd = cargo_command("build", &project.path, &CargoOptions::default());
+    assert!(!args(&cmd).contains(&"--locked".to_string()));
+    assert!(args(&cmd).contains(&"--offline".to_string()));
+
+    fs::write(project.path.join("Cargo.lock"), "version = 3\n").unwrap();
+    let cmd = cargo_command("build", &project.path, &CargoOptions::default());
+    assert!(args(&cmd).contains(&"--locked".to_string()));
+
+    let options = CargoOptions {
+        locked: false,
+        offline: false,
+        ..CargoOptions::default()
+    };
+    let cmd = cargo_command("build", &project.path, &options);
+    assert!(!args(&cmd).iter().any(|a| a == "--locked" || a == "--offline"));
+}
+
+#[test]
+fn test_cargo_options_from_config() {
+    let config = Config::parse("[cargo]\nlocked = false\n").unwrap();
+    assert_eq!(
+        config.cargo,
+        CargoOptions {
+            locked: false,
+            offline: true,
+            ..CargoOptions::default()
+        }
+    );
+    assert!(Config::parse("[cargo]\nfrozen = true\n").is_err());
+    assert!(Config::parse("[cargo]\noffline = \"yes\"\n").is_err());
+}
+
+#[test]
+fn test_project_files_report_changes() {
+    let project = TestProject::new("test_cargo_guard_changes").with_basic_cargo_toml();
+
+    let files = ProjectFiles::snapshot(&project.path);
+    assert!(files.changes().is_empty());
+
+    fs::write(project.path.join("Cargo.lock"), "version = 3\n").unwrap();
+    assert_eq!(files.changes(), ["Cargo.lock was created"]);
+
+    let files = ProjectFiles::snapshot(&project.path);
+    fs::write(project.path.join("Cargo.lock"), "version = 4\n").unwrap();
+    assert_eq!(files.changes(), ["Cargo.lock was modified"]);
+}
+
+#[test]
+fn test_stale_lock_file_is_not_rewritten() {
+    let project = TestProject::new("test_cargo_guard_stale_lock")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+
+    let mut session = Session::new(Some(&project.path));
+    session.add_code_block("let x = 1;");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+
+    // Changing the package version makes the lock file stale.
+    let manifest = project.path.join("Cargo.toml");
+    let text = fs::read_to_string(&manifest).unwrap();
+    fs::write(&manifest, text.replace("version = \"0.1.0\"", "version = \"0.2.0\"")).unwrap();
+    let lock = fs::read(project.path.join("Cargo.lock")).unwrap();
+
+    session.add_code_block("let y = 2;");
+    assert_eq!(session.run().unwrap(), RunOutcome::CompileError);
+    assert_eq!(fs::read(project.path.join("Cargo.lock")).unwrap(), lock);
+}
@@ -0,0 +1,60 @@
+use rsh::rsh::{handle_command, parse_script, Input, RunOutcome, Session};
+use std::fs;
+
+mod common;
+use common::TestProject;
+
+#[test]
+fn test_check_candidate_block_without_running() {
+    let project = TestProject::new("test_check_candidate")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+    let runs = project.path.join("runs.txt");
+
+    let mut session = Session::new(Some(&project.path));
+    session.add_code_block(&format!(
+        "use std::io::Write;\nlet mut log = std::fs::OpenOptions::new().create(true).append(true).open({:?}).unwrap();\nwriteln!(log, \"run\").unwrap();\nlet total: i32 = 40;",
+        runs
+    ));
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+    assert_eq!(fs::read_to_string(&runs).unwrap().lines().count(), 1);
+    let (preamble, body) = (session.preamble().to_vec(), session.body().to_vec());
+
+    assert!(session.check(None).unwrap());
+    assert!(session.check(Some("fn add(a: i32, b: i32) -> i32 { a + b }\nassert_eq!(add(total, 2), 42);")).unwrap());
+    assert!(!session.check(Some("let wrong: String = total;")).unwrap());
+
+    // Nothing ran, and the session is unchanged.
+    assert_eq!(fs::read_to_string(&runs).unwrap().lines().count(), 1);
+    assert_eq!(session.preamble(), preamble);
+    assert_eq!(session.body(), body);
+
+    // The next real block builds on the session as it was.
+    session.add_code_block("assert_eq!(total, 40);");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+    session.cleanup();
+}
+
+#[test]
+fn test_check_command_forms() {
+    let project = TestProject::new("test_check_command")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+
+    let mut session = Session::new(Some(&project.path));
+    handle_command(":check { let x: u8 = 1; }", &mut session).unwrap();
+    handle_command(":check {\n    let v = vec![1, 2];\n\n    assert_eq!(v.len(), 2);\n}", &mut session).unwrap();
+    handle_command(":check", &mut session).unwrap();
+    assert!(session.body().is_empty());
+    session.cleanup();
+}
+
+#[test]
+fn test_check_block_spans_lines_in_scripts() {
+    let items = parse_script(":check {\n    let x = 1;\n\n    let y = x + 1;\n}\nlet z = 3;\n\n:check\n");
+    assert_eq!(items.len(), 3);
+    assert_eq!(items[0].line, 1);
+    assert!(matches!(&items[0].input, Input::Command(cmd) if cmd == ":check {\n    let x = 1;\n\n    let y = x + 1;\n}"));
+    assert!(matches!(&items[1].input, Input::Code(code) if code == "let z = 3;"));
+    assert!(matches!(&items[2].input, Input::Command(cmd) if cmd == ":check"));
+}
@@ -0,0 +1,92 @@
+use rsh::rsh::{parse_args, CargoOptions, ModeRequest, Options, RunOutcome, Session};
+use std::fs;
+use std::path::PathBuf;
+
+mod common;
+use common::TestProject;
+
+fn parse(args: &[&str]) -> Result<Options, String> {
+    parse_args(args.iter().map(|s| s.to_string()))
+}
+
+#[test]
+fn test_parse_args_options() {
+    let options = parse(&[
+        "--project",
+        "../app",
+        "--features=fancy,extra",
+        "--features",
+        "more",
+        "--release",
+        "--no-cleanup",
+        "--history-file",
+        "hist.txt",
+        "--load",
+        "a.rsh",
+        "--load=b.rsh",
+        "--exec",
+        "println!(\"hi\");",
+    ])
+    .unwrap();
+    assert_eq!(options.project, Some(PathBuf::from("../app")));
+    assert_eq!(options.features, ["fancy", "extra", "more"]);
+    assert!(options.release && options.no_cleanup);
+    assert_eq!(options.history_file, Some(PathBuf::from("hist.txt")));
+    assert_eq!(options.load, [PathBuf::from("a.rsh"), PathBuf::from("b.rsh")]);
+    assert_eq!(options.exec, ["println!(\"hi\");"]);
+
+    assert!(parse(&["--help"]).unwrap().help);
+    assert!(parse(&["-h"]).unwrap().help);
+    assert!(parse(&["--version"]).unwrap().version);
+}
+
+#[test]
+fn test_parse_args_runtime_and_conflicts() {
+    let options = parse(&["--runtime", "tokio current_thread"]).unwrap();
+    assert_eq!(
+        options.mode,
+        Some(ModeRequest::Async {
+            runtime: Some("tokio".to_string()),
+            flavor: Some("current_thread".to_string()),
+        })
+    );
+    assert!(parse(&["--runtime", ""]).is_err());
+    assert!(parse(&["--mode", "sync", "--runtime", "tokio"]).is_err());
+    assert!(parse(&["--standalone", "--project", "."]).is_err());
+    assert!(parse(&["run", "x.rsh", "--exec", "1;"]).is_err());
+}
+
+#[test]
+fn test_features_and_release_reach_the_build() {
+    let project = TestProject::new("test_cli_features").with_basic_cargo_toml().with_main_rs();
+    let manifest = project.path.join("Cargo.toml");
+    let text = fs::read_to_string(&manifest).unwrap();
+    fs::write(&manifest, format!("{text}\n[features]\nfancy = []\n")).unwrap();
+
+    let mut session = Session::new(Some(&project.path));
+    session.set_cargo_options(CargoOptions {
+        features: vec!["fancy".to_string()],
+        release: true,
+        ..CargoOptions::default()
+    });
+    session.add_code_block("assert!(cfg!(feature = \"fancy\"));\nassert!(!cfg!(debug_assertions));");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+
+    // Without the feature and in a debug build, the session's assertions fail.
+    session.set_cargo_options(CargoOptions::default());
+    session.add_code_block("let _x = 1;");
+    assert_ne!(session.run().unwrap(), RunOutcome::Success);
+    session.cleanup();
+}
+
+#[test]
+fn test_no_cleanup_keeps_generated_file() {
+    let project = TestProject::new("test_cli_no_cleanup").with_basic_cargo_toml().with_main_rs();
+
+    let mut session = Session::new(Some(&project.path));
+    session.set_cleanup(false);
+    session.add_code_block("let _x = 1;");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+    session.cleanup();
+    assert!(session.rsh_path().exists());
+}
@@ -0,0 +1,37 @@
+use rsh::rsh::{RunOutcome, Session};
+use std::fs;
+
+mod common;
+use common::TestProject;
+
+#[test]
+fn test_sessions_in_one_project_get_their_own_targets() {
+    let project = TestProject::new("test_concurrent_sessions")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+
+    let mut first = Session::new(Some(&project.path));
+    let mut second = Session::new(Some(&project.path));
+    assert_eq!(first.target_name(), "__rsh");
+    assert_eq!(second.target_name(), "__rsh_1");
+
+    first.add_code_block("let who = \"first\";\nassert_eq!(who, \"first\");");
+    second.add_code_block("let who = \"second\";\nassert_eq!(who, \"second\");");
+    assert_eq!(first.run().unwrap(), RunOutcome::Success);
+    assert_eq!(second.run().unwrap(), RunOutcome::Success);
+
+    assert!(fs::read_to_string(first.rsh_path()).unwrap().contains("\"first\""));
+    assert!(fs::read_to_string(second.rsh_path()).unwrap().contains("\"second\""));
+
+    let sessions = first.sessions();
+    assert_eq!(sessions.len(), 2);
+    assert_eq!(sessions[1].target, "__rsh_1");
+    assert_eq!(sessions[1].pid, Some(std::process::id()));
+
+    // A finished session frees its slot for the next one.
+    second.cleanup();
+    drop(second);
+    assert_eq!(first.sessions().len(), 1);
+    let third = Session::new(Some(&project.path));
+    assert_eq!(third.target_name(), "__rsh_1");
+}
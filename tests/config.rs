@@ -0,0 +1,98 @@
+use rsh::rsh::{handle_command, ColorChoice, CommandResult, Config, FailureKind, FailurePolicy, ModeRequest, RuntimeRegistry, Session};
+use std::fs;
+
+mod common;
+use common::TestProject;
+
+#[test]
+fn test_project_config_overrides_user_config_key_by_key() {
+    let project = TestProject::new("test_config_layers").with_basic_cargo_toml();
+    let user = project.path.join("user.toml");
+    fs::write(
+        &user,
+        "mode = \"async tokio\"\ncolor = \"never\"\n[cargo]\noffline = false\nrelease = true\n[aliases]\ns = \":stats\"\nq2 = \":quit\"\n",
+    )
+    .unwrap();
+    let local = project.path.join(".rsh.toml");
+    fs::write(&local, "mode = \"sync\"\n[cargo]\nrelease = false\n[aliases]\ns = \":show\"\n").unwrap();
+
+    let config = Config::load_layers(&[user.clone(), local.clone(), project.path.join("missing.toml")]).unwrap();
+    assert_eq!(config.mode, Some(ModeRequest::Sync));
+    assert_eq!(config.color, ColorChoice::Never);
+    assert!(!config.cargo.offline && !config.cargo.release);
+    assert_eq!(config.aliases["s"], ":show");
+    assert_eq!(config.aliases["q2"], ":quit");
+
+    assert_eq!(config.source("mode"), Some(local.as_path()));
+    assert_eq!(config.source("color"), Some(user.as_path()));
+    assert_eq!(config.source("cargo.offline"), Some(user.as_path()));
+    assert_eq!(config.source("cargo.release"), Some(local.as_path()));
+    assert_eq!(config.source("aliases.q2"), Some(user.as_path()));
+    assert_eq!(config.source("layout"), None);
+    assert!(config.entries().contains(&("layout".to_string(), "\"single\"".to_string())));
+
+    // Errors name the file they are in.
+    fs::write(&local, "colour = \"never\"\n").unwrap();
+    let err = Config::load_layers(&[user, local]).unwrap_err().to_string();
+    assert!(err.contains(".rsh.toml") && err.contains("unknown key `colour`"), "{err}");
+}
+
+#[test]
+fn test_config_keys_are_validated() {
+    let config = Config::parse(
+        "runtime-order = [\"smol\"]\nprelude = [\"use std::fmt;\"]\n[on-failure]\ncompile = \"keep\"\n[cargo]\nfeatures = [\"a\", \"b\"]\n",
+    )
+    .unwrap();
+    assert_eq!(config.runtime_order, ["smol"]);
+    assert_eq!(config.prelude, Some(vec!["use std::fmt;".to_string()]));
+    assert_eq!(config.on_compile_failure, Some(FailurePolicy::Keep));
+    assert_eq!(config.cargo.features, ["a", "b"]);
+
+    assert!(Config::parse("prelude = \"use std::fmt;\"\n").is_err());
+    assert!(Config::parse("color = \"sometimes\"\n").is_err());
+    assert!(Config::parse("[on-failure]\nlink = \"keep\"\n").is_err());
+    assert!(Config::parse("[aliases]\ns = \"show\"\n").is_err());
+}
+
+#[test]
+fn test_runtime_order_changes_detection_preference() {
+    let mut registry = RuntimeRegistry::builtin();
+    assert_eq!(registry.prefer(&["smol".to_string(), "nope".to_string()]), ["nope"]);
+    assert_eq!(&registry.names()[..3], ["smol", "tokio", "async-std"]);
+}
+
+#[test]
+fn test_session_applies_project_config() {
+    let project = TestProject::new("test_config_session")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+    fs::write(
+        project.path.join(".rsh.toml"),
+        r#"prelude = ["use std::collections::BTreeMap;"]
+preamble-prefixes = ["macro_rules!"]
+prompt = "[{mode}]{status} "
+
+[on-failure]
+runtime = "keep"
+
+[aliases]
+r = ":reset"
+"#,
+    )
+    .unwrap();
+
+    let mut session = Session::new(Some(&project.path));
+    assert_eq!(session.failure_policy(FailureKind::Runtime), FailurePolicy::Keep);
+    assert_eq!(session.prompt(), "[sync] ");
+
+    session.add_code_block("macro_rules! twice {\n    ($e:expr) => { $e * 2 };\n}\nlet m: BTreeMap<i32, i32> = BTreeMap::new();\nassert_eq!(twice!(m.len()), 0);");
+    assert_eq!(session.preamble().len(), 3);
+    let code = session.generate_rsh_bin().unwrap();
+    assert!(code.contains("use std::collections::BTreeMap;\n\nmacro_rules! twice"), "{code}");
+    assert_eq!(session.run().unwrap(), rsh::rsh::RunOutcome::Success);
+
+    assert_eq!(handle_command(":r", &mut session).unwrap(), CommandResult::Handled);
+    assert!(session.body().is_empty());
+    assert_eq!(handle_command(":nope", &mut session).unwrap(), CommandResult::Unknown);
+    session.cleanup();
+}
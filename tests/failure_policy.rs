@@ -0,0 +1,62 @@
+use rsh::rsh::{FailureKind, FailurePolicy, RunOutcome, Session};
+
+mod common;
+use common::TestProject;
+
+#[test]
+fn test_runtime_failure_can_be_kept_while_compile_errors_roll_back() {
+    let project = TestProject::new("test_failure_policy_keep")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+
+    let mut session = Session::new(Some(&project.path));
+    session.set_failure_policy(FailureKind::Runtime, FailurePolicy::Keep);
+
+    session.add_code_block("let x = 1;");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+
+    session.add_code_block("debug_assert!(x == 2);");
+    assert_eq!(session.run().unwrap(), RunOutcome::Panicked);
+    assert_eq!(session.pending_failure(), None);
+
+    session.add_code_block("let y: i32 = \"not a number\";");
+    assert_eq!(session.run().unwrap(), RunOutcome::CompileError);
+
+    assert_eq!(session.body(), ["let x = 1;", "debug_assert!(x == 2);"]);
+}
+
+#[test]
+fn test_ask_leaves_failed_block_pending_until_resolved() {
+    let project = TestProject::new("test_failure_policy_ask")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+
+    let mut session = Session::new(Some(&project.path));
+    session.set_failure_policy(FailureKind::Compile, FailurePolicy::Ask);
+    session.set_failure_policy(FailureKind::Runtime, FailurePolicy::Ask);
+
+    session.add_code_block("let x = 1;");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+    assert_eq!(session.pending_failure(), None);
+
+    session.add_code_block("let y: i32 = \"not a number\";");
+    assert_eq!(session.run().unwrap(), RunOutcome::CompileError);
+    assert_eq!(session.pending_failure(), Some("let y: i32 = \"not a number\";"));
+    session.drop_last_block();
+    assert_eq!(session.pending_failure(), None);
+    assert_eq!(session.body(), ["let x = 1;"]);
+
+    session.add_code_block("std::process::exit(3);");
+    assert_eq!(session.run().unwrap(), RunOutcome::Failed(Some(3)));
+    session.keep_last_block();
+    assert_eq!(session.pending_failure(), None);
+    assert_eq!(session.body(), ["let x = 1;", "std::process::exit(3);"]);
+}
+
+#[test]
+fn test_failure_policy_parse() {
+    assert_eq!(FailurePolicy::parse("rollback"), Some(FailurePolicy::Rollback));
+    assert_eq!(FailurePolicy::parse("keep"), Some(FailurePolicy::Keep));
+    assert_eq!(FailurePolicy::parse("ask"), Some(FailurePolicy::Ask));
+    assert_eq!(FailurePolicy::parse("retry"), None);
+}
@@ -0,0 +1,109 @@
+use rsh::rsh::{handle_command, RunOutcome, Segment, Session};
+use std::fs;
+
+mod common;
+use common::TestProject;
+
+#[test]
+fn test_clippy_lints_point_at_session_lines() {
+    let project = TestProject::new("test_clippy_lints")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+
+    let mut session = Session::new(Some(&project.path));
+    session.add_code_block("fn answer() -> i32 { return 42; }\nlet v = vec![1, 2, 3];\nlet n = v.len() == 0;\nassert!(!n && answer() == 42);");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+
+    let lints = session.clippy().unwrap();
+    let needless_return = lints
+        .iter()
+        .find(|lint| lint.diagnostic.message.contains("unneeded `return`"))
+        .expect("needless_return lint");
+    assert_eq!((needless_return.segment, needless_return.index), (Segment::Preamble, 0));
+    let len_zero = lints
+        .iter()
+        .find(|lint| lint.diagnostic.message.contains("length comparison to zero"))
+        .expect("len_zero lint");
+    assert_eq!((len_zero.segment, len_zero.index), (Segment::Body, 1));
+    session.cleanup();
+}
+
+#[test]
+fn test_clippy_reports_a_session_that_does_not_compile() {
+    let project = TestProject::new("test_clippy_broken")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+
+    let mut session = Session::new(Some(&project.path));
+    session.add_code_block("let x: i32 = \"no\";");
+    assert!(session.clippy().is_err());
+    session.cleanup();
+}
+
+#[test]
+fn test_fmt_keeps_preamble_and_body_apart() {
+    let project = TestProject::new("test_fmt_session")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+
+    let mut session = Session::new(Some(&project.path));
+    session.add_code_block("use std::collections::HashMap;\nstruct Point{x:i32,y:i32}\nlet p=Point{x:1,y:2};\nlet mut m=HashMap::new();\nm.insert(p.x,p.y);");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+
+    assert!(session.format().unwrap());
+    assert_eq!(
+        session.preamble(),
+        ["use std::collections::HashMap;", "struct Point {", "    x: i32,", "    y: i32,", "}"]
+    );
+    assert_eq!(
+        session.body(),
+        ["let p = Point { x: 1, y: 2 };", "let mut m = HashMap::new();", "m.insert(p.x, p.y);"]
+    );
+    assert!(!session.format().unwrap());
+
+    // The formatted session still runs, and later blocks add to it.
+    session.add_code_block("assert_eq!(m[&1], 2);");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+    session.cleanup();
+}
+
+#[test]
+fn test_fmt_keeps_multi_line_string_literals() {
+    let project = TestProject::new("test_fmt_string_literals")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+    let out = project.path.join("out.txt");
+
+    let mut session = Session::new(Some(&project.path));
+    session.add_code_block(&format!(
+        "let s = \"a\n    b\";\nlet r = r#\"c\n    d\"#;\nlet q = '\"';\nstd::fs::write({:?}, format!(\"{{s}}|{{r}}|{{q}}\")).unwrap();",
+        out
+    ));
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+    let before = fs::read_to_string(&out).unwrap();
+
+    session.format().unwrap();
+    assert_eq!(session.body()[..4], ["let s = \"a", "    b\";", "let r = r#\"c", "    d\"#;"]);
+
+    // The strings hold the same text after formatting.
+    session.add_code_block("let x = 1;");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+    assert_eq!(fs::read_to_string(&out).unwrap(), before);
+    session.cleanup();
+}
+
+#[test]
+fn test_fmt_leaves_unparsable_code_alone() {
+    let project = TestProject::new("test_fmt_unparsable")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+
+    let mut session = Session::new(Some(&project.path));
+    handle_command(":fmt", &mut session).unwrap();
+    assert!(session.body().is_empty());
+
+    session.add_code_block("let x = (1;");
+    let body = session.body().to_vec();
+    assert!(session.format().is_err());
+    assert_eq!(session.body(), body);
+}
@@ -0,0 +1,92 @@
+use rsh::rsh::{handle_command, Logger, Manifest, RunOutcome, Session};
+use std::fs;
+
+mod common;
+use common::TestProject;
+
+fn manifest(dependencies: &str) -> Manifest {
+    Manifest::parse(&format!("[package]\nname = \"p\"\nversion = \"0.1.0\"\n\n[dependencies]\n{dependencies}")).unwrap()
+}
+
+#[test]
+fn test_detect_logger_prefers_tracing_subscriber() {
+    assert_eq!(Logger::detect(&manifest("log = \"0.4\"\n")), None);
+    assert_eq!(Logger::detect(&manifest("env_logger = \"0.11\"\n")), Some(Logger::EnvLogger));
+    assert_eq!(
+        Logger::detect(&manifest("env_logger = \"0.11\"\ntracing-subscriber = \"0.3\"\n")),
+        Some(Logger::TracingSubscriber)
+    );
+    // Without default features there is no `fmt` subscriber to install.
+    assert_eq!(
+        Logger::detect(&manifest("tracing-subscriber = { version = \"0.3\", default-features = false }\n")),
+        None
+    );
+}
+
+#[test]
+fn test_logger_init_code() {
+    let m = manifest("tracing-subscriber = { version = \"0.3\", features = [\"env-filter\"] }\nsimple_logger = \"4\"\n");
+    let tracing = Logger::TracingSubscriber.init(m.dependency("tracing-subscriber").unwrap());
+    assert!(tracing.contains("tracing_subscriber::EnvFilter::from_default_env()"), "{tracing}");
+    let simple = Logger::SimpleLogger.init(m.dependency("simple_logger").unwrap());
+    assert_eq!(simple, "let _ = simple_logger::SimpleLogger::new().env().init();");
+}
+
+#[test]
+fn test_log_filter_reaches_env_logger() {
+    let project = TestProject::new("test_logging_env_logger").with_main_rs();
+    fs::write(
+        project.path.join("Cargo.toml"),
+        "[package]\nname = \"test-project\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\nlog = \"0.4\"\nenv_logger = \"0.11\"\n",
+    )
+    .unwrap();
+    project.fetch_dependencies();
+
+    let mut session = Session::new(Some(&project.path));
+    handle_command(":env RUST_LOG=info", &mut session).unwrap();
+    assert_eq!(session.logger(), Some(Logger::EnvLogger));
+    session.add_code_block("log::info!(\"hello from the session\");\nlet debug = std::env::var(\"RUST_LOG\").unwrap() == \"debug\";\nassert_eq!(log::log_enabled!(log::Level::Debug), debug);");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+    assert!(project.read_rsh_bin().contains("    let _ = env_logger::try_init();\n"));
+
+    // The filter changes between runs.
+    handle_command(":log debug", &mut session).unwrap();
+    assert_eq!(session.log_filter().as_deref(), Some("debug"));
+    session.add_code_block("assert!(debug);");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+    session.cleanup();
+}
+
+#[test]
+fn test_optional_logger_only_when_enabled() {
+    let project = TestProject::new("test_logging_optional").with_main_rs();
+    let cargo_toml = |default: &str| {
+        format!("[package]\nname = \"test-project\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\nenv_logger = {{ version = \"0.11\", optional = true }}\n\n[features]\ndefault = [{default}]\n")
+    };
+    fs::write(project.path.join("Cargo.toml"), cargo_toml("")).unwrap();
+    project.fetch_dependencies();
+
+    let mut session = Session::new(Some(&project.path));
+    assert_eq!(session.logger(), None);
+    session.add_code_block("let x = 1;");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+    assert!(!project.read_rsh_bin().contains("env_logger"));
+    session.cleanup();
+
+    fs::write(project.path.join("Cargo.toml"), cargo_toml("\"env_logger\"")).unwrap();
+    let session = Session::new(Some(&project.path));
+    assert_eq!(session.logger(), Some(Logger::EnvLogger));
+}
+
+#[test]
+fn test_no_logger_without_logging_crate() {
+    let project = TestProject::new("test_logging_none")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+
+    let mut session = Session::new(Some(&project.path));
+    assert_eq!(session.logger(), None);
+    assert!(!session.generate_rsh_bin().unwrap().contains("try_init"));
+    handle_command(":log warn", &mut session).unwrap();
+    assert_eq!(session.log_filter().as_deref(), Some("warn"));
+}
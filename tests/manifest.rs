@@ -0,0 +1,141 @@
+use rsh::rsh::{Manifest, Session, Mode, AsyncRuntime, RuntimeRegistry};
+use std::fs;
+
+mod common;
+use common::TestProject;
+
+#[test]
+fn test_manifest_ignores_comments_and_similar_names() {
+    let manifest = Manifest::parse(
+        r#"[package]
+name = "demo"
+version = "0.1.0"
+
+# tokio would be nice here some day
+[dependencies]
+tokio-util = "0.7"
+smoltcp = "0.11"
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(manifest.package_name.as_deref(), Some("demo"));
+    assert!(manifest.dependency("tokio").is_none());
+    assert!(manifest.dependency("smol").is_none());
+    assert_eq!(RuntimeRegistry::builtin().detect(&manifest), None);
+}
+
+#[test]
+fn test_manifest_reads_features_and_renames() {
+    let manifest = Manifest::parse(
+        r#"[package]
+name = "demo"
+version = "0.1.0"
+
+[dependencies]
+rt = { package = "tokio", version = "1", features = ["rt", "macros"], default-features = false }
+
+[target.'cfg(unix)'.dependencies]
+async-std = "1.12"
+"#,
+    )
+    .unwrap();
+
+    let tokio = manifest.dependency("tokio").unwrap();
+    assert_eq!(tokio.name, "rt");
+    assert!(tokio.has_feature("macros"));
+    assert!(!tokio.default_features);
+
+    let async_std = manifest.dependency("async-std").unwrap();
+    assert_eq!(async_std.name, "async_std");
+    assert!(async_std.default_features);
+}
+
+#[test]
+fn test_optional_dependency_needs_resolution() {
+    let manifest = Manifest::parse(
+        r#"[package]
+name = "demo"
+version = "0.1.0"
+
+[dependencies]
+anyhow = { version = "1", optional = true }
+log = "0.4"
+"#,
+    )
+    .unwrap();
+
+    // Whether an optional dependency is enabled is only known once cargo resolved it.
+    assert!(manifest.dependencies.iter().any(|d| d.package == "anyhow" && d.optional));
+    assert!(manifest.dependency("anyhow").is_none());
+    assert!(manifest.dependency("log").is_some());
+}
+
+#[test]
+fn test_detect_skips_tokio_without_rt() {
+    let manifest = Manifest::parse(
+        r#"[package]
+name = "demo"
+version = "0.1.0"
+
+[dependencies]
+tokio = { version = "1", features = ["sync"] }
+smol = "1.0"
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(RuntimeRegistry::builtin().detect(&manifest), Some(AsyncRuntime::new("smol")));
+}
+
+#[test]
+fn test_tokio_without_macros_uses_runtime_builder() {
+    let project = TestProject::new("test_tokio_no_macros").with_main_rs();
+    let cargo_toml = r#"[package]
+name = "test-project"
+version = "0.1.0"
+edition = "2021"
+
+[dependencies]
+tokio = { version = "1.0", features = ["rt"] }
+"#;
+    fs::write(project.path.join("Cargo.toml"), cargo_toml).unwrap();
+    project.fetch_dependencies();
+
+    let mut session = Session::new(Some(&project.path));
+    session.add_code_block("async fn answer() -> u32 { 42 }\nstd::fs::write(\"answer.txt\", answer().await.to_string())?;");
+    let _ = session.run();
+
+    assert!(matches!(session.mode(), Mode::Async(rt) if rt.name() == "tokio"));
+
+    let generated = project.read_rsh_bin();
+    assert!(!generated.contains("#[tokio::main"));
+    assert!(generated.contains("tokio::runtime::Builder::new_current_thread()"));
+
+    // The fallback main actually compiles and runs.
+    let answer = fs::read_to_string(project.path.join("answer.txt")).unwrap();
+    assert_eq!(answer, "42");
+}
+
+#[test]
+fn test_tokio_current_thread_flavor_without_multi_thread() {
+    let project = TestProject::new("test_tokio_current_thread").with_main_rs();
+    let cargo_toml = r#"[package]
+name = "test-project"
+version = "0.1.0"
+edition = "2021"
+
+[dependencies]
+tokio = { version = "1.0", features = ["rt", "macros"] }
+"#;
+    fs::write(project.path.join("Cargo.toml"), cargo_toml).unwrap();
+    project.fetch_dependencies();
+
+    let mut session = Session::new(Some(&project.path));
+    session.add_code_block("async fn test() {}\ntest().await;\nstd::fs::write(\"ran.txt\", \"ok\")?;");
+    let _ = session.run();
+
+    let generated = project.read_rsh_bin();
+    assert!(generated.contains("#[tokio::main(flavor = \"current_thread\")]"));
+    assert!(project.path.join("ran.txt").exists());
+}
@@ -0,0 +1,130 @@
+use rsh::rsh::{parse_args, AsyncRuntime, Config, Mode, ModeRequest, RunOutcome, Session};
+use std::fs;
+
+mod common;
+use common::TestProject;
+
+#[test]
+fn test_mode_request_parse() {
+    assert_eq!(ModeRequest::parse(&["sync"]), Ok(ModeRequest::Sync));
+    assert_eq!(
+        ModeRequest::parse(&["async"]),
+        Ok(ModeRequest::Async { runtime: None, flavor: None })
+    );
+    assert_eq!(
+        ModeRequest::parse(&["async", "tokio", "current_thread"]),
+        Ok(ModeRequest::Async {
+            runtime: Some("tokio".to_string()),
+            flavor: Some("current_thread".to_string()),
+        })
+    );
+    assert!(ModeRequest::parse(&["sync", "tokio"]).is_err());
+    assert!(ModeRequest::parse(&["turbo"]).is_err());
+}
+
+#[test]
+fn test_mode_flag_parsing() {
+    let options = parse_args(["--mode".to_string(), "async".to_string()]).unwrap();
+    assert_eq!(options.mode, Some(ModeRequest::Async { runtime: None, flavor: None }));
+
+    let options = parse_args(["--mode=sync".to_string()]).unwrap();
+    assert_eq!(options.mode, Some(ModeRequest::Sync));
+
+    assert!(parse_args(["--mode".to_string()]).is_err());
+    assert!(parse_args(["--mode=fast".to_string()]).is_err());
+    assert!(parse_args(["--bogus".to_string()]).is_err());
+}
+
+#[test]
+fn test_config_default_mode() {
+    let config = Config::parse("mode = \"async tokio current_thread\"\n").unwrap();
+    assert_eq!(
+        config.mode,
+        Some(ModeRequest::Async {
+            runtime: Some("tokio".to_string()),
+            flavor: Some("current_thread".to_string()),
+        })
+    );
+    assert!(Config::parse("mode = \"fast\"\n").is_err());
+}
+
+#[test]
+fn test_project_default_mode_preselects_async() {
+    let project = TestProject::new("test_project_default_mode")
+        .with_tokio()
+        .with_main_rs();
+    fs::write(project.path.join(".rsh.toml"), "mode = \"async\"\n").unwrap();
+    project.fetch_dependencies();
+
+    let mut session = Session::new(Some(&project.path));
+    assert_eq!(session.mode(), &Mode::Async(AsyncRuntime::new("tokio")));
+
+    // :reset goes back to the configured mode, not sync.
+    assert!(session.switch_mode(Mode::Sync).unwrap());
+    session.reset();
+    assert_eq!(session.mode(), &Mode::Async(AsyncRuntime::new("tokio")));
+}
+
+#[test]
+fn test_switch_mode_on_empty_session_does_not_build() {
+    let project = TestProject::new("test_switch_mode_empty")
+        .with_tokio()
+        .with_main_rs();
+    project.fetch_dependencies();
+
+    let mut session = Session::new(Some(&project.path));
+    let mode = session.resolve_mode(&ModeRequest::Async { runtime: None, flavor: None }).unwrap();
+    assert!(session.switch_mode(mode).unwrap());
+    assert!(matches!(session.mode(), Mode::Async(rt) if rt.name() == "tokio"));
+    assert!(!project.rsh_bin_path().exists());
+}
+
+#[test]
+fn test_switch_back_to_sync_rolls_back_when_session_needs_async() {
+    let project = TestProject::new("test_switch_back_to_sync")
+        .with_tokio()
+        .with_main_rs();
+    project.fetch_dependencies();
+
+    let mut session = Session::new(Some(&project.path));
+    session.set_runtime("tokio", None).unwrap();
+    session.add_code_block("async fn test() {}\ntest().await;");
+    let _ = session.run();
+    assert_eq!(session.body().len(), 2);
+
+    // `.await` can't compile in sync mode: the switch is rolled back.
+    assert!(!session.switch_mode(Mode::Sync).unwrap());
+    assert!(matches!(session.mode(), Mode::Async(rt) if rt.name() == "tokio"));
+    assert!(project.read_rsh_bin().contains("#[tokio::main]"));
+
+    // Once the async code is gone, switching back succeeds.
+    session.reset();
+    session.set_runtime("tokio", None).unwrap();
+    session.add_code_block("let x = 1;");
+    assert!(session.switch_mode(Mode::Sync).unwrap());
+    assert!(matches!(session.mode(), Mode::Sync));
+}
+
+#[test]
+fn test_switch_mode_builds_without_running() {
+    let project = TestProject::new("test_switch_mode_no_run")
+        .with_tokio()
+        .with_main_rs();
+    project.fetch_dependencies();
+    let runs = project.path.join("runs.txt");
+
+    let mut session = Session::new(Some(&project.path));
+    session.add_code_block(&format!(
+        "use std::io::Write;\nlet mut log = std::fs::OpenOptions::new().create(true).append(true).open({:?}).unwrap();\nwriteln!(log, \"run\").unwrap();",
+        runs
+    ));
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+    assert_eq!(fs::read_to_string(&runs).unwrap().lines().count(), 1);
+
+    let mode = session.resolve_mode(&ModeRequest::Async { runtime: None, flavor: None }).unwrap();
+    assert!(session.switch_mode(mode).unwrap());
+    assert!(project.read_rsh_bin().contains("#[tokio::main]"));
+    assert!(session.switch_mode(Mode::Sync).unwrap());
+    assert_eq!(fs::read_to_string(&runs).unwrap().lines().count(), 1);
+    session.cleanup();
+}
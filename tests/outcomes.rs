@@ -0,0 +1,141 @@
+use rsh::rsh::{CargoOptions, RunOutcome, Session};
+use std::fs;
+
+mod common;
+use common::TestProject;
+
+#[test]
+fn test_outcomes_are_distinguished_and_failures_rolled_back() {
+    let project = TestProject::new("test_outcomes_distinguished")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+
+    let mut session = Session::new(Some(&project.path));
+
+    session.add_code_block("let x = 1;");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+
+    session.add_code_block("let y: i32 = \"not a number\";");
+    assert_eq!(session.run().unwrap(), RunOutcome::CompileError);
+
+    session.add_code_block("let n: i32 = \"nope\".parse()?;");
+    assert_eq!(session.run().unwrap(), RunOutcome::ReturnedErr);
+
+    session.add_code_block("panic!(\"boom\");");
+    assert_eq!(session.run().unwrap(), RunOutcome::Panicked);
+
+    session.add_code_block("std::process::exit(3);");
+    assert_eq!(session.run().unwrap(), RunOutcome::Failed(Some(3)));
+
+    // Exiting with 1 is not mistaken for a returned `Err`.
+    session.add_code_block("std::process::exit(1);");
+    assert_eq!(session.run().unwrap(), RunOutcome::Failed(Some(1)));
+
+    // Only the successful block is kept.
+    assert_eq!(session.body(), ["let x = 1;"]);
+}
+
+#[test]
+fn test_generated_main_reports_err_with_debug_and_exit_code() {
+    let project = TestProject::new("test_generated_main_exit_code")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+
+    let mut session = Session::new(Some(&project.path));
+    session.add_code_block("let x = 1;");
+    let _ = session.run();
+
+    let generated = project.read_rsh_bin();
+    assert!(generated.contains("fn __rsh_session() -> std::result::Result<(), Box<dyn std::error::Error>>"));
+    assert!(generated.contains("eprintln!(\"Error: {:?}\", e);"));
+    assert!(generated.contains("std::process::exit(213);"));
+}
+
+#[test]
+fn test_anyhow_result_type_when_host_depends_on_anyhow() {
+    let project = TestProject::new("test_anyhow_result_type").with_main_rs();
+    let cargo_toml = r#"[package]
+name = "test-project"
+version = "0.1.0"
+edition = "2021"
+
+[dependencies]
+anyhow = "1"
+"#;
+    fs::write(project.path.join("Cargo.toml"), cargo_toml).unwrap();
+    project.fetch_dependencies();
+
+    let mut session = Session::new(Some(&project.path));
+    session.add_code_block("use anyhow::Context;\nlet n: i32 = \"nope\".parse().context(\"parsing the answer\")?;");
+    assert_eq!(session.run().unwrap(), RunOutcome::ReturnedErr);
+
+    let generated = project.read_rsh_bin();
+    assert!(generated.contains("fn __rsh_session() -> anyhow::Result<()>"));
+}
+
+#[test]
+fn test_disabled_optional_anyhow_keeps_boxed_error() {
+    let project = TestProject::new("test_optional_anyhow").with_main_rs();
+    let cargo_toml = r#"[package]
+name = "test-project"
+version = "0.1.0"
+edition = "2021"
+
+[dependencies]
+anyhow = { version = "1", optional = true }
+"#;
+    fs::write(project.path.join("Cargo.toml"), cargo_toml).unwrap();
+    project.fetch_dependencies();
+
+    let mut session = Session::new(Some(&project.path));
+    session.add_code_block("let x = 1;");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+    assert!(project
+        .read_rsh_bin()
+        .contains("fn __rsh_session() -> std::result::Result<(), Box<dyn std::error::Error>>"));
+}
+
+#[test]
+fn test_built_binary_runs_directly_and_reports_status() {
+    let project = TestProject::new("test_run_report")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+
+    let mut session = Session::new(Some(&project.path));
+    session.add_code_block("let v = vec![0u8; 1 << 20];\nprintln!(\"{}\", v.len());");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+    let report = *session.last_run().unwrap();
+    assert_eq!(report.status.code(), Some(0));
+    assert_eq!(report.signal(), None);
+    if cfg!(unix) {
+        assert!(report.peak_rss.unwrap() > 0);
+    }
+
+    session.add_code_block("std::process::abort();");
+    if cfg!(unix) {
+        assert_eq!(session.run().unwrap(), RunOutcome::Failed(None));
+        assert_eq!(session.last_run().unwrap().signal(), Some(6));
+    }
+}
+
+#[test]
+fn test_build_output_names_the_executable() {
+    let project = TestProject::new("test_build_output_executable")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+
+    let mut session = Session::new(Some(&project.path));
+    session.add_code_block("let x = 1;");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+
+    let build = rsh::rsh::utils::build_cargo_rsh(&project.path, "__rsh", &CargoOptions::default()).unwrap();
+    assert!(build.success());
+    let executable = build.executable.unwrap();
+    assert!(executable.is_file());
+    assert!(executable.file_stem().unwrap() == "__rsh");
+
+    fs::write(project.path.join("src/bin/__rsh.rs"), "fn main() { let x: i32 = \"no\"; }").unwrap();
+    let build = rsh::rsh::utils::build_cargo_rsh(&project.path, "__rsh", &CargoOptions::default()).unwrap();
+    assert!(!build.success());
+    assert!(build.stderr.contains("error[E0308]"));
+}
@@ -0,0 +1,87 @@
+use rsh::rsh::{handle_command, RunOutcome, Session};
+use std::fs;
+
+mod common;
+use common::TestProject;
+
+#[test]
+fn test_default_prelude_imports_host_library() {
+    let project = TestProject::new("test_prelude_default")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+    fs::write(project.path.join("src/lib.rs"), "pub fn answer() -> i32 { 42 }\n").unwrap();
+
+    let mut session = Session::new(Some(&project.path));
+    assert_eq!(session.prelude(), ["use test_project::*;"]);
+    session.add_code_block("assert_eq!(answer(), 42);");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+
+    // The prelude is generated ahead of the PREAMBLE but isn't part of it.
+    let code = session.generate_rsh_bin().unwrap();
+    assert!(code.contains("#[allow(unused_imports)]\nuse test_project::*;"), "{code}");
+    assert!(session.preamble().is_empty());
+    session.cleanup();
+}
+
+#[test]
+fn test_library_result_alias_does_not_shadow_session_result() {
+    let project = TestProject::new("test_prelude_result_alias")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+    fs::write(
+        project.path.join("src/lib.rs"),
+        "pub type Result<T> = std::result::Result<T, String>;\npub fn v() -> Result<i32> { Ok(42) }\n",
+    )
+    .unwrap();
+
+    let mut session = Session::new(Some(&project.path));
+    session.add_code_block("let n: i32 = \"7\".parse()?;\nassert_eq!(v().unwrap() + n, 49);");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+    session.cleanup();
+}
+
+#[test]
+fn test_no_default_prelude_without_library() {
+    let project = TestProject::new("test_prelude_no_lib")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+
+    let session = Session::new(Some(&project.path));
+    assert!(session.prelude().is_empty());
+}
+
+#[test]
+fn test_config_prelude_replaces_default() {
+    let project = TestProject::new("test_prelude_config")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+    fs::write(project.path.join("src/lib.rs"), "pub fn answer() -> i32 { 42 }\n").unwrap();
+    fs::write(project.path.join(".rsh.toml"), "prelude = []\n").unwrap();
+
+    let session = Session::new(Some(&project.path));
+    assert!(session.prelude().is_empty());
+}
+
+#[test]
+fn test_prelude_add_and_remove() {
+    let project = TestProject::new("test_prelude_commands")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+
+    let mut session = Session::new(Some(&project.path));
+    handle_command(":prelude add use std::collections::{HashMap, HashSet};", &mut session).unwrap();
+    assert_eq!(session.prelude(), ["use std::collections::{HashMap, HashSet};"]);
+    assert!(session.add_prelude("use std::collections::{HashMap, HashSet};").is_err());
+    assert!(session.add_prelude("let x = 1;").is_err());
+
+    // Unused prelude imports don't fail the build, and :reset keeps the prelude.
+    session.add_code_block("let m: HashMap<i32, i32> = HashMap::new();\nassert!(m.is_empty());");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+    handle_command(":reset", &mut session).unwrap();
+    assert_eq!(session.prelude().len(), 1);
+
+    handle_command(":prelude remove 0", &mut session).unwrap();
+    assert!(session.prelude().is_empty());
+    assert!(session.remove_prelude(0).is_err());
+    session.cleanup();
+}
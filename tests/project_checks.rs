@@ -0,0 +1,77 @@
+use rsh::rsh::project::{exclude_from_git, GENERATED_MARKER};
+use rsh::rsh::{parse_args, RunOutcome, Session};
+use std::fs;
+
+mod common;
+use common::TestProject;
+
+#[test]
+fn test_refuses_directory_without_cargo_toml() {
+    let project = TestProject::new("test_check_no_manifest");
+    let session = Session::new(Some(&project.path));
+    let err = session.check_project().unwrap_err();
+    assert!(err.contains("no Cargo.toml"));
+}
+
+#[test]
+fn test_refuses_to_overwrite_user_written_rsh_bin() {
+    let project = TestProject::new("test_check_user_rsh_bin")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+    fs::create_dir_all(project.path.join("src/bin")).unwrap();
+    let user_file = project.path.join("src/bin/__rsh.rs");
+    fs::write(&user_file, "fn main() { println!(\"mine\"); }\n").unwrap();
+
+    let mut session = Session::new(Some(&project.path));
+    assert!(session.check_project().unwrap_err().contains("not generated by rsh"));
+
+    session.add_code_block("let x = 1;");
+    assert!(session.run().is_err());
+    session.cleanup();
+    assert_eq!(fs::read_to_string(&user_file).unwrap(), "fn main() { println!(\"mine\"); }\n");
+}
+
+#[test]
+fn test_generated_file_carries_marker_and_is_reused() {
+    let project = TestProject::new("test_check_marker")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+
+    let mut session = Session::new(Some(&project.path));
+    session.check_project().unwrap();
+    session.add_code_block("let x = 1;");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+    assert!(project.read_rsh_bin().starts_with(GENERATED_MARKER));
+
+    // A file left behind by an earlier session is fine.
+    drop(session);
+    let session = Session::new(Some(&project.path));
+    assert_eq!(session.target_name(), "__rsh");
+    session.check_project().unwrap();
+}
+
+#[test]
+fn test_generated_path_added_to_git_exclude_once() {
+    let project = TestProject::new("test_check_git_exclude")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+    fs::create_dir_all(project.path.join(".git")).unwrap();
+
+    let rsh_bin = project.path.join("src/bin/__rsh.rs");
+    assert!(exclude_from_git(&rsh_bin).unwrap());
+    assert!(!exclude_from_git(&rsh_bin).unwrap());
+
+    let exclude = fs::read_to_string(project.path.join(".git/info/exclude")).unwrap();
+    assert_eq!(exclude.lines().filter(|l| *l == "/src/bin/__rsh.rs").count(), 1);
+}
+
+#[test]
+fn test_standalone_session_runs_in_throwaway_project() {
+    assert!(parse_args(["--standalone".to_string()]).unwrap().standalone);
+
+    let mut session = Session::standalone().unwrap();
+    session.check_project().unwrap();
+    session.add_code_block("let x = 2 + 2;\nassert_eq!(x, 4);");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+    session.cleanup();
+}
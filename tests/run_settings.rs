@@ -0,0 +1,98 @@
+use rsh::rsh::{RunOutcome, Session, split_args};
+use std::fs;
+
+mod common;
+use common::TestProject;
+
+#[test]
+fn test_split_args_handles_quotes() {
+    let args = split_args("one \"two three\" 'four' five").unwrap();
+    assert_eq!(args, vec!["one", "two three", "four", "five"]);
+
+    assert!(split_args("").unwrap().is_empty());
+    assert!(split_args("\"unterminated").is_err());
+}
+
+#[test]
+fn test_env_and_unenv_are_recorded() {
+    let project = TestProject::new("test_env_recorded")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+
+    let mut session = Session::new(Some(&project.path));
+
+    session.set_env("RSH_TEST", "1");
+    session.unset_env("HOME");
+
+    let env = &session.settings().env;
+    assert_eq!(env.get("RSH_TEST"), Some(&Some("1".to_string())));
+    assert_eq!(env.get("HOME"), Some(&None));
+
+    // Run settings survive a reset.
+    session.reset();
+    assert_eq!(session.settings().env.len(), 2);
+}
+
+#[test]
+fn test_cwd_and_stdin_reject_missing_paths() {
+    let project = TestProject::new("test_cwd_stdin_missing")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+
+    let mut session = Session::new(Some(&project.path));
+
+    assert!(session.set_cwd(Some("does-not-exist")).is_err());
+    assert!(session.set_stdin(Some("does-not-exist.txt")).is_err());
+    assert!(session.settings().cwd.is_none());
+    assert!(session.settings().stdin.is_none());
+
+    assert!(session.set_cwd(Some("src")).is_ok());
+    assert!(session.settings().cwd.as_ref().unwrap().ends_with("src"));
+    assert!(session.set_cwd(None::<&str>).is_ok());
+    assert!(session.settings().cwd.is_none());
+}
+
+#[test]
+fn test_removed_cwd_fails_the_block() {
+    let project = TestProject::new("test_cwd_removed")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+    fs::create_dir_all(project.path.join("work")).unwrap();
+
+    let mut session = Session::new(Some(&project.path));
+    session.set_cwd(Some("work")).unwrap();
+    fs::remove_dir(project.path.join("work")).unwrap();
+
+    session.add_code_block("let x = 1;");
+    assert_eq!(session.run().unwrap(), RunOutcome::NotRun);
+    assert!(session.body().is_empty());
+    session.cleanup();
+}
+
+#[test]
+fn test_run_settings_reach_generated_program() {
+    let project = TestProject::new("test_run_settings_program")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+    fs::create_dir_all(project.path.join("work")).unwrap();
+    fs::write(project.path.join("input.txt"), "from stdin").unwrap();
+
+    let mut session = Session::new(Some(&project.path));
+    session.set_env("RSH_GREETING", "hello");
+    session.set_args(vec!["alpha".to_string(), "beta gamma".to_string()]);
+    session.set_cwd(Some("work")).unwrap();
+    session.set_stdin(Some("input.txt")).unwrap();
+
+    session.add_code_block(
+        "use std::io::Read;\n\
+         let args: Vec<String> = std::env::args().skip(1).collect();\n\
+         let mut input = String::new();\n\
+         std::io::stdin().read_to_string(&mut input)?;\n\
+         let report = format!(\"{}|{}|{}\", std::env::var(\"RSH_GREETING\")?, args.join(\",\"), input);\n\
+         std::fs::write(\"report.txt\", report)?;",
+    );
+    assert!(session.run().is_ok());
+
+    let report = fs::read_to_string(project.path.join("work/report.txt")).unwrap();
+    assert_eq!(report, "hello|alpha,beta gamma|from stdin");
+}
@@ -0,0 +1,150 @@
+use rsh::rsh::{AsyncRuntime, Config, Manifest, Mode, RuntimeRegistry, Session};
+use std::fs;
+
+mod common;
+use common::TestProject;
+
+const USER_RUNTIME: &str = r#"[[runtime]]
+name = "blocking"
+package = "pollster"
+main = """
+fn main() {
+    {crate}::block_on(async {
+        {session}
+    });
+}
+"""
+"#;
+
+#[test]
+fn test_builtin_preference_order() {
+    let manifest = Manifest::parse(
+        r#"[package]
+name = "demo"
+version = "0.1.0"
+
+[dependencies]
+futures = "0.3"
+actix-rt = "2"
+"#,
+    )
+    .unwrap();
+
+    // A real runtime wins over a plain executor crate.
+    assert_eq!(RuntimeRegistry::builtin().detect(&manifest), Some(AsyncRuntime::new("actix-rt")));
+}
+
+#[test]
+fn test_builtin_backends_generate_main() {
+    let registry = RuntimeRegistry::builtin();
+
+    let futures = registry.main(&AsyncRuntime::new("futures"), None).unwrap();
+    assert!(futures.contains("futures::executor::block_on(async {"));
+
+    let actix = registry.main(&AsyncRuntime::new("actix-rt"), None).unwrap();
+    assert!(actix.contains("actix_rt::System::new().block_on(async {"));
+
+    let tokio = registry
+        .main(&AsyncRuntime::with_flavor("tokio", Some("current_thread")), None)
+        .unwrap();
+    assert!(tokio.contains("#[tokio::main(flavor = \"current_thread\")]"));
+
+    assert!(registry.main(&AsyncRuntime::new("no-such-runtime"), None).is_none());
+}
+
+#[test]
+fn test_user_runtime_parsed_and_preferred() {
+    let config = Config::parse(USER_RUNTIME).unwrap();
+    assert_eq!(config.runtimes.len(), 1);
+    assert_eq!(config.runtimes[0].package, "pollster");
+
+    let registry = RuntimeRegistry::with_user_runtimes(&config.runtimes);
+    assert_eq!(registry.names()[0], "blocking");
+
+    let manifest = Manifest::parse(
+        r#"[package]
+name = "demo"
+version = "0.1.0"
+
+[dependencies]
+pollster = "0.3"
+smol = "1"
+"#,
+    )
+    .unwrap();
+    assert_eq!(registry.detect(&manifest), Some(AsyncRuntime::new("blocking")));
+
+    let main = registry.main(&AsyncRuntime::new("blocking"), Some(&manifest)).unwrap();
+    assert!(main.contains("    pollster::block_on(async {\n        if let std::result::Result::Err(e) = __rsh_session().await {"));
+}
+
+#[test]
+fn test_user_runtime_requires_main() {
+    assert!(Config::parse("[[runtime]]\nname = \"broken\"\n").is_err());
+}
+
+#[test]
+fn test_set_runtime_validates_name_flavor_and_dependency() {
+    let project = TestProject::new("test_set_runtime_validation")
+        .with_tokio()
+        .with_main_rs();
+
+    let mut session = Session::new(Some(&project.path));
+
+    assert!(session.set_runtime("no-such-runtime", None).is_err());
+    assert!(session.set_runtime("tokio", Some("turbo")).is_err());
+    assert!(session.set_runtime("smol", None).is_err());
+    assert!(matches!(session.mode(), Mode::Sync));
+
+    session.set_runtime("tokio", Some("current_thread")).unwrap();
+    assert_eq!(
+        session.mode(),
+        &Mode::Async(AsyncRuntime::with_flavor("tokio", Some("current_thread")))
+    );
+}
+
+#[test]
+fn test_tokio_multi_thread_needs_rt_multi_thread_feature() {
+    let project = TestProject::new("test_tokio_flavor_features").with_main_rs();
+    fs::write(
+        project.path.join("Cargo.toml"),
+        "[package]\nname = \"test-project\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\ntokio = { version = \"1.0\", features = [\"rt\", \"macros\"] }\n",
+    )
+    .unwrap();
+    project.fetch_dependencies();
+
+    let mut session = Session::new(Some(&project.path));
+    let err = session.set_runtime("tokio", Some("multi_thread")).unwrap_err();
+    assert!(err.contains("rt-multi-thread"), "{err}");
+    assert!(matches!(session.mode(), Mode::Sync));
+
+    session.set_runtime("tokio", Some("current_thread")).unwrap();
+    assert_eq!(
+        session.mode(),
+        &Mode::Async(AsyncRuntime::with_flavor("tokio", Some("current_thread")))
+    );
+}
+
+#[test]
+fn test_user_runtime_from_project_config_runs() {
+    let project = TestProject::new("test_user_runtime_runs").with_main_rs();
+    let cargo_toml = r#"[package]
+name = "test-project"
+version = "0.1.0"
+edition = "2021"
+
+[dependencies]
+pollster = "0.3"
+"#;
+    fs::write(project.path.join("Cargo.toml"), cargo_toml).unwrap();
+    fs::write(project.path.join(".rsh.toml"), USER_RUNTIME).unwrap();
+    project.fetch_dependencies();
+
+    let mut session = Session::new(Some(&project.path));
+    session.add_code_block("async fn answer() -> u32 { 42 }\nstd::fs::write(\"answer.txt\", answer().await.to_string())?;");
+    let _ = session.run();
+
+    assert!(matches!(session.mode(), Mode::Async(rt) if rt.name() == "blocking"));
+    let answer = fs::read_to_string(project.path.join("answer.txt")).unwrap();
+    assert_eq!(answer, "42");
+}
@@ -0,0 +1,100 @@
+use rsh::rsh::{parse_args, FailurePolicy, Layout, ModeRequest, RunOutcome, RunSettings, SavedSession, Session};
+use std::fs;
+
+mod common;
+use common::TestProject;
+
+#[test]
+fn test_saved_session_round_trips_through_toml() {
+    let mut settings = RunSettings {
+        args: vec!["--verbose".to_string(), "two words".to_string()],
+        cwd: Some("/tmp/data".into()),
+        ..RunSettings::default()
+    };
+    settings.env.insert("API_URL".to_string(), Some("http://localhost:8080".to_string()));
+    settings.env.insert("RUST_LOG".to_string(), None);
+
+    let saved = SavedSession {
+        preamble: vec!["struct Point {".to_string(), "    x: i32,".to_string(), "}".to_string()],
+        body: vec!["let s = \"quoted \\\" text\";".to_string()],
+        mode: ModeRequest::Async {
+            runtime: Some("tokio".to_string()),
+            flavor: Some("current_thread".to_string()),
+        },
+        layout: Layout::Split,
+        settings,
+        on_compile_failure: FailurePolicy::Keep,
+        on_runtime_failure: FailurePolicy::Ask,
+    };
+
+    let text = saved.to_toml();
+    assert!(text.contains("mode = \"async tokio current_thread\""), "{text}");
+    assert_eq!(SavedSession::parse(&text).unwrap(), saved);
+}
+
+#[test]
+fn test_named_sessions_save_open_and_list() {
+    let project = TestProject::new("test_session_store_named")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+
+    let mut session = Session::new(Some(&project.path));
+    session.add_code_block("use std::collections::HashMap;\nlet mut seen: HashMap<i32, i32> = HashMap::new();");
+    session.set_args(vec!["a".to_string()]);
+    session.set_env("GREETING", "hi");
+    session.set_failure_policy(rsh::rsh::FailureKind::Runtime, FailurePolicy::Keep);
+    let path = session.save_session("explore").unwrap();
+    assert!(path.ends_with(".rsh/sessions/explore.toml"));
+    assert!(session.save_session("../escape").is_err());
+    drop(session);
+
+    let mut other = Session::new(Some(&project.path));
+    assert!(other.open_session("missing").is_err());
+    other.open_session("explore").unwrap();
+    assert_eq!(other.preamble(), ["use std::collections::HashMap;"]);
+    assert_eq!(other.body(), ["let mut seen: HashMap<i32, i32> = HashMap::new();"]);
+    assert_eq!(other.settings().args, ["a"]);
+    assert_eq!(other.settings().env.get("GREETING"), Some(&Some("hi".to_string())));
+    assert_eq!(other.failure_policy(rsh::rsh::FailureKind::Runtime), FailurePolicy::Keep);
+
+    let names: Vec<String> = other.saved_sessions().into_iter().map(|s| s.name).collect();
+    assert_eq!(names, ["explore"]);
+}
+
+#[test]
+fn test_successful_runs_are_autosaved_and_resumed() {
+    let project = TestProject::new("test_session_store_autosave")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+
+    let mut crashed = Session::new(Some(&project.path));
+    crashed.add_code_block("let answer = 42;");
+    assert_eq!(crashed.run().unwrap(), RunOutcome::Success);
+    // A failing block is rolled back and not saved.
+    crashed.add_code_block("assert_eq!(answer, 0);");
+    assert_eq!(crashed.run().unwrap(), RunOutcome::Panicked);
+    drop(crashed);
+
+    let autosaves = fs::read_dir(project.path.join(".rsh/autosave")).unwrap().count();
+    assert_eq!(autosaves, 1);
+
+    let mut resumed = Session::new(Some(&project.path));
+    resumed.resume().unwrap();
+    assert_eq!(resumed.body(), ["let answer = 42;"]);
+
+    // The restored session keeps running where it left off.
+    resumed.add_code_block("assert_eq!(answer, 42);");
+    assert_eq!(resumed.run().unwrap(), RunOutcome::Success);
+    resumed.cleanup();
+}
+
+#[test]
+fn test_resume_without_autosave_fails() {
+    let project = TestProject::new("test_session_store_empty")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+
+    let mut session = Session::new(Some(&project.path));
+    assert!(session.resume().is_err());
+    assert!(parse_args(["--resume".to_string()]).unwrap().resume);
+}
@@ -0,0 +1,90 @@
+use rsh::rsh::split::publicize;
+use rsh::rsh::{Layout, RunOutcome, Session};
+use std::fs;
+
+mod common;
+use common::TestProject;
+
+fn lines(text: &str) -> Vec<String> {
+    text.lines().map(str::to_string).collect()
+}
+
+#[test]
+fn test_publicize_items_fields_and_inherent_methods() {
+    let preamble = lines(
+        "use std::fmt;\n\
+         #[derive(Debug)]\n\
+         struct Point {\n\
+         x: i32,\n\
+         pub y: i32,\n\
+         }\n\
+         struct Pair(i32, Vec<(u8, u8)>);\n\
+         struct Inline { a: u8, f: fn(u8) -> u8 }\n\
+         impl Point {\n\
+         fn new() -> Self { Point { x: 0, y: 0 } }\n\
+         }\n\
+         impl fmt::Display for Point {\n\
+         fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, \"{}\", self.x) }\n\
+         }\n\
+         macro_rules! twice { ($e:expr) => { $e * 2 }; }",
+    );
+
+    let expected = "pub use std::fmt;\n\
+                    #[derive(Debug)]\n\
+                    pub struct Point {\n\
+                    pub x: i32,\n\
+                    pub y: i32,\n\
+                    }\n\
+                    pub struct Pair(pub i32, pub Vec<(u8, u8)>);\n\
+                    pub struct Inline { pub a: u8, pub f: fn(u8) -> u8 }\n\
+                    impl Point {\n\
+                    pub fn new() -> Self { Point { x: 0, y: 0 } }\n\
+                    }\n\
+                    impl fmt::Display for Point {\n\
+                    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, \"{}\", self.x) }\n\
+                    }\n\
+                    #[macro_export]\n\
+                    macro_rules! twice { ($e:expr) => { $e * 2 }; }\n";
+    assert_eq!(publicize(&preamble), expected);
+}
+
+#[test]
+fn test_split_layout_rebuilds_preamble_only_when_it_changes() {
+    let project = TestProject::new("test_split_layout")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+
+    let mut session = Session::new(Some(&project.path));
+    session.set_layout(Layout::Split);
+
+    session.add_code_block(
+        "struct Point {\n    x: i32,\n}\nimpl Point {\n    fn double(&self) -> i32 { self.x * 2 }\n}",
+    );
+    session.add_code_block("let p = Point { x: 21 };\nassert_eq!(p.double(), 42);");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+    assert!(session.last_build().unwrap().preamble_elapsed.is_some());
+
+    session.add_code_block("assert_eq!(p.x, 21);");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+    assert!(session.last_build().unwrap().preamble_elapsed.is_none());
+
+    // The host project's bin directory is left alone.
+    assert!(!project.path.join("src/bin/__rsh.rs").exists());
+    let main = fs::read_to_string(session.split_workspace().join("body/main.rs")).unwrap();
+    assert!(main.contains("#[allow(unused_imports)]\nuse rsh_preamble::*;"));
+
+    session.add_code_block("fn broken() -> i32 { \"no\" }");
+    assert_eq!(session.run().unwrap(), RunOutcome::CompileError);
+    assert_eq!(session.layout(), Layout::Split);
+}
+
+#[test]
+fn test_layout_from_config() {
+    let project = TestProject::new("test_layout_config")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+    fs::write(project.path.join(".rsh.toml"), "layout = \"split\"\n").unwrap();
+
+    let session = Session::new(Some(&project.path));
+    assert_eq!(session.layout(), Layout::Split);
+}
@@ -0,0 +1,32 @@
+use rsh::rsh::{RunOutcome, Segment, Session};
+
+mod common;
+use common::TestProject;
+
+#[test]
+fn test_stats_record_each_block() {
+    let project = TestProject::new("test_stats")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+
+    let mut session = Session::new(Some(&project.path));
+    session.add_code_block("let x = 1;");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+    session.add_code_block("let y: i32 = \"no\";");
+    assert_eq!(session.run().unwrap(), RunOutcome::CompileError);
+    session.delete(Segment::Body, &[0]);
+    session.add_code_block("let x = 1;");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+
+    let stats = session.stats();
+    assert_eq!(stats.blocks.len(), 3);
+    assert_eq!(stats.blocks[0].first_line, "let x = 1;");
+    assert!(stats.blocks[0].run.is_some());
+    assert_eq!(stats.blocks[1].outcome, RunOutcome::CompileError);
+    assert_eq!(stats.blocks[1].run, None);
+    assert!(stats.blocks[2].cached);
+    assert_eq!(
+        stats.total_build(),
+        stats.blocks.iter().map(|b| b.build).sum()
+    );
+}
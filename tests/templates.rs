@@ -0,0 +1,88 @@
+use rsh::rsh::{Config, Mode, RunOutcome, Session, Template, TemplateContext};
+use std::fs;
+
+mod common;
+use common::TestProject;
+
+#[test]
+fn test_template_render_places_lines_at_indent() {
+    let template = Template::parse("{preamble}\nfn f() -> {error_type} {\n    {body}\n}\n{main}\n").unwrap();
+    let code = template.render(&TemplateContext {
+        preamble: &["use std::fmt;".to_string(), String::new()],
+        init: &[],
+        body: &["let x = 1;".to_string(), "let y = { x };".to_string()],
+        main: "fn main() {\n    f();\n}\n",
+        error_type: "()",
+        runtime: "",
+    });
+    assert_eq!(
+        code,
+        "use std::fmt;\n\nfn f() -> () {\n    let x = 1;\n    let y = { x };\n}\nfn main() {\n    f();\n}\n"
+    );
+}
+
+#[test]
+fn test_templates_must_place_preamble_body_and_main() {
+    assert!(Template::parse("fn main() { {body} }\n{preamble}\n").is_err());
+    assert!(!Template::parse("{preamble}\n{body}\n{main}\n").unwrap().has_init());
+    assert!(Template::sync().has_init() && Template::async_().has_init());
+    assert!(Config::parse("[templates]\nsync = \"{body}\\n\"\n").is_err());
+    assert!(Config::parse("[templates]\nbatch = \"{preamble}\\n{body}\\n{main}\\n\"\n").is_err());
+    assert!(Config::parse("[templates]\nasync = \"{preamble}\\n  {body}\\n\"\n").is_err());
+    assert!(Config::parse("[templates]\nasync = \"{preamble}\\n  {body}\\n{main}\\n\"\n").unwrap().async_template.is_some());
+}
+
+#[test]
+fn test_project_sync_template_wraps_body() {
+    let project = TestProject::new("test_templates_sync")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+    fs::write(
+        project.path.join(".rsh.toml"),
+        r#"[templates]
+sync = """
+static SETUP: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+{preamble}
+fn __rsh_session() -> {error_type} {
+    SETUP.store(true, std::sync::atomic::Ordering::SeqCst);
+    let session = || -> {error_type} {
+        {body}
+        Ok(())
+    };
+    session()
+}
+
+{main}
+"""
+"#,
+    )
+    .unwrap();
+
+    let mut session = Session::new(Some(&project.path));
+    session.add_code_block("fn double(x: i32) -> i32 { x * 2 }\nassert!(SETUP.load(std::sync::atomic::Ordering::SeqCst));\nassert_eq!(double(2), 4);");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+    session.add_code_block("assert_eq!(double(2), 5);");
+    assert_ne!(session.run().unwrap(), RunOutcome::Success);
+
+    let code = session.generate_rsh_bin().unwrap();
+    assert!(code.contains("\n        assert_eq!(double(2), 4);\n"), "{code}");
+    assert!(code.contains("\nfn double(x: i32) -> i32 { x * 2 }\n"), "{code}");
+    session.cleanup();
+}
+
+#[test]
+fn test_async_template_gets_runtime_name() {
+    let project = TestProject::new("test_templates_async").with_tokio().with_main_rs();
+    fs::write(
+        project.path.join(".rsh.toml"),
+        "mode = \"async tokio\"\n[templates]\nasync = \"\"\"\n// runtime: {runtime}\n{preamble}\nasync fn __rsh_session() -> {error_type} {\n    {body}\n    Ok(())\n}\n{main}\n\"\"\"\n",
+    )
+    .unwrap();
+
+    let session = Session::new(Some(&project.path));
+    assert!(matches!(session.mode(), Mode::Async(_)));
+    let code = session.generate_rsh_bin().unwrap();
+    assert!(code.contains("// runtime: tokio\n"), "{code}");
+    assert!(code.contains("#[tokio::main]") || code.contains("tokio::runtime"), "{code}");
+}
@@ -0,0 +1,32 @@
+use rsh::rsh::{RunOutcome, Session};
+
+mod common;
+use common::TestProject;
+
+#[test]
+fn test_first_block_waits_for_warmup_build() {
+    let project = TestProject::new("test_warmup")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+
+    let mut session = Session::new(Some(&project.path));
+    session.start_warmup().unwrap();
+    assert!(project.path.join("src/bin/__rsh.rs").exists());
+
+    session.add_code_block("let x = 40 + 2;\nassert_eq!(x, 42);");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+    assert!(!session.warming_up());
+}
+
+#[test]
+fn test_cleanup_stops_running_warmup() {
+    let project = TestProject::new("test_warmup_cleanup")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+
+    let mut session = Session::new(Some(&project.path));
+    session.start_warmup().unwrap();
+    session.cleanup();
+    assert!(!session.warming_up());
+    assert!(!project.path.join("src/bin/__rsh.rs").exists());
+}
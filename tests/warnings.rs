@@ -0,0 +1,97 @@
+use rsh::rsh::{handle_command, Config, Layout, RunOutcome, Session, Warnings};
+
+mod common;
+use common::TestProject;
+
+/// Messages of the warnings the last build printed.
+fn shown_warnings(session: &Session) -> Vec<String> {
+    let build = session.last_build().unwrap();
+    session
+        .shown_diagnostics(build)
+        .into_iter()
+        .filter(|d| d.level == "warning")
+        .map(|d| d.rendered.clone())
+        .collect()
+}
+
+#[test]
+fn test_only_newest_block_warnings_are_shown() {
+    let project = TestProject::new("test_warnings_newest")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+
+    let mut session = Session::new(Some(&project.path));
+    session.add_code_block("let unused = 1;\nlet _a = (1);");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+    let shown = shown_warnings(&session);
+    assert_eq!(shown.len(), 1, "{shown:?}");
+    assert!(shown[0].contains("unnecessary parentheses"), "{shown:?}");
+
+    // The first block's warning is still emitted, but no longer shown.
+    session.add_code_block("fn helper() {}\nlet _b = (2);");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+    let build = session.last_build().unwrap();
+    assert!(build.diagnostics.iter().filter(|d| d.message.contains("unnecessary parentheses")).count() >= 2);
+    let shown = shown_warnings(&session);
+    assert_eq!(shown.len(), 1, "{shown:?}");
+    assert!(shown[0].contains("(2)"), "{shown:?}");
+
+    let code = session.generate_rsh_bin().unwrap();
+    assert!(code.contains("#![allow(unused_variables, unused_imports, unused_mut, unused_assignments, dead_code)]\n"), "{code}");
+    session.cleanup();
+}
+
+#[test]
+fn test_warnings_command_changes_lint_attributes() {
+    let project = TestProject::new("test_warnings_command")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+
+    let mut session = Session::new(Some(&project.path));
+    handle_command(":warnings deny", &mut session).unwrap();
+    assert_eq!(session.warnings(), Warnings::Deny);
+    // Noise lints stay allowed, other warnings fail the build.
+    session.add_code_block("let unused = 1;");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+    session.add_code_block("let _a = (1);");
+    assert_eq!(session.run().unwrap(), RunOutcome::CompileError);
+
+    handle_command(":warnings off", &mut session).unwrap();
+    session.add_code_block("let _a = (1);");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+    assert!(session.last_build().unwrap().diagnostics.iter().all(|d| d.level != "warning"));
+
+    handle_command(":warnings on", &mut session).unwrap();
+    session.add_code_block("let also_unused = 2;");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+    let shown = shown_warnings(&session);
+    assert!(shown.iter().any(|w| w.contains("also_unused")), "{shown:?}");
+    assert!(!shown.iter().any(|w| w.contains("`unused`")), "{shown:?}");
+    session.cleanup();
+}
+
+#[test]
+fn test_split_layout_shows_newest_block_warnings() {
+    let project = TestProject::new("test_warnings_split")
+        .with_basic_cargo_toml()
+        .with_main_rs();
+
+    let mut session = Session::new(Some(&project.path));
+    session.set_layout(Layout::Split);
+    session.add_code_block("let _a = (1);");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+    session.add_code_block("struct Point { x: i32 }\nlet _b = (2);");
+    assert_eq!(session.run().unwrap(), RunOutcome::Success);
+    let shown = shown_warnings(&session);
+    assert_eq!(shown.len(), 1, "{shown:?}");
+    assert!(shown[0].contains("(2)"), "{shown:?}");
+    session.cleanup();
+}
+
+#[test]
+fn test_warnings_config_key() {
+    assert_eq!(Config::parse("warnings = \"off\"\n").unwrap().warnings, Warnings::Off);
+    assert_eq!(Config::parse("").unwrap().warnings, Warnings::Default);
+    assert!(Config::parse("warnings = \"loud\"\n").is_err());
+    assert_eq!(Warnings::On.attributes(), Vec::<String>::new());
+}